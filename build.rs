@@ -21,7 +21,10 @@ fn main() -> Result<(), std::io::Error> {
     }
 
     fn get_run_test() -> String {
-        include_str!("build_templates/run_test.rs").to_string()
+        //the doc comment at the end of the template documents the tests module, so no blank line in between
+        include_str!("build_templates/run_test.rs")
+            .trim_end()
+            .to_string()
     }
 
     fn write_test(file: &mut std::fs::File, content: &str) -> Result<(), std::io::Error> {
@@ -43,7 +46,7 @@ fn main() -> Result<(), std::io::Error> {
         let mut dirs = get_test_dirs(root_dir).expect("Unable to read tests directory");
         let mut result = vec!["".to_string()];
 
-        dirs.retain(|d| (d.is_dir()));
+        dirs.retain(|d| d.is_dir());
 
         for d in dirs.iter() {
             if let Ok(mut _dir) = d.read_dir() {
@@ -2,14 +2,9 @@ extern crate scheduler;
 
 use std::{fs, path::Path};
 
-use chrono::NaiveDateTime;
-use serde::Deserialize;
 use serde_json::Value;
 
-use scheduler::{
-    models::{goal::Goal, task::TaskCompletedToday},
-    run_scheduler,
-};
+use scheduler::{run_scheduler_with_input, technical::input_output::Input};
 
 fn main() {
     println!("Running!");
@@ -19,19 +14,5 @@ fn main() {
     dbg!(&json);
     let input: Input = serde_json::from_value(json).unwrap();
     dbg!(&input);
-    let _ = run_scheduler(
-        input.start_date,
-        input.end_date,
-        &input.goals,
-        input.tasks_completed_today,
-    );
-}
-
-#[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields, rename_all = "camelCase")]
-struct Input {
-    start_date: NaiveDateTime,
-    end_date: NaiveDateTime,
-    goals: Vec<Goal>,
-    tasks_completed_today: Vec<TaskCompletedToday>,
+    let _ = run_scheduler_with_input(&input);
 }
@@ -12,7 +12,7 @@
 //       "TODO_working_example"
 //     });
 //     let input: Input = serde_json::from_value(json_input)?;
-//     let output = scheduler::run_scheduler(input);
+//     let output = scheduler::run_scheduler_with_input(&input);
 // ```
 //!
 //! ## Getting Started
@@ -55,7 +55,8 @@
 use crate::models::activity::Activity;
 use activity_generator::{
     add_budget_min_day_activities, add_budget_min_week_activities,
-    add_budget_top_up_week_activities, add_simple_activities, add_tasks_completed_today,
+    add_budget_top_up_week_activities, add_missed_activities, add_simple_activities,
+    add_tasks_completed_today, allow_soft_deadline_activities_to_be_late,
 };
 use activity_placer::{place, place_postponed_as_best_effort};
 use chrono::NaiveDateTime;
@@ -64,6 +65,7 @@ use models::{calendar::Calendar, goal::Goal, task::FinalTasks};
 use serde_wasm_bindgen::{from_value, to_value};
 use services::activity_generator;
 use services::activity_placer;
+use services::verifier;
 use std::collections::BTreeMap;
 use technical::input_output::Input;
 use wasm_bindgen::prelude::*;
@@ -89,15 +91,21 @@ pub fn schedule(input: &JsValue) -> Result<JsValue, JsError> {
     console_error_panic_hook::set_once();
     // JsError implements From<Error>, so we can just use `?` on any Error
     let input: Input = from_value(input.clone())?;
-    let final_tasks = run_scheduler(
-        input.start_date,
-        input.end_date,
-        &input.goals,
-        input.tasks_completed_today,
-    );
+    let final_tasks = run_scheduler_with_input(&input);
     Ok(to_value(&final_tasks)?)
 }
 
+/// Checks a schedule (possibly edited by hand) against the constraints of its input
+#[wasm_bindgen]
+pub fn verify(input: &JsValue, output: &JsValue) -> Result<JsValue, JsError> {
+    console_error_panic_hook::set_once();
+    let input: Input = from_value(input.clone())?;
+    let output: FinalTasks = from_value(output.clone())?;
+    let violations = verifier::verify(&input, &output);
+    Ok(to_value(&violations)?)
+}
+
+/// Schedules goals without any of the optional input, like tag caps or a now cut-off
 #[must_use]
 pub fn run_scheduler(
     start_date: NaiveDateTime,
@@ -105,27 +113,53 @@ pub fn run_scheduler(
     goals: &[Goal],
     tasks_completed_today: Vec<TaskCompletedToday>,
 ) -> FinalTasks {
-    let mut calendar = Calendar::new(start_date, end_date);
+    run_scheduler_with_input(&Input {
+        start_date,
+        end_date,
+        goals: goals.to_vec(),
+        tasks_completed_today,
+        ..Input::default()
+    })
+}
+
+#[must_use]
+pub fn run_scheduler_with_input(input: &Input) -> FinalTasks {
+    let mut calendar = Calendar::new(input.start_date, input.end_date);
+    calendar.week_start = input.week_start;
     let mut activities: Vec<Activity> = vec![];
     let mut goal_map: BTreeMap<String, Goal> = BTreeMap::new(); //Don't use hashmap as that doesn't guarantee ordering - messing up determinacy of tests
-    for goal in goals {
+    for goal in &input.goals {
         //optimize this out if frontend already has a map? - probably won't have any significant effect => measure
         goal_map.insert(goal.id.clone(), goal.clone());
     }
 
     calendar.add_budgets_from(&mut goal_map);
+    calendar.add_tag_caps(&input.tag_caps, &goal_map);
+    calendar.add_goal_caps(&goal_map);
+    calendar.add_day_constraints(&goal_map);
+    calendar.add_workload_limits(input.max_hours_per_day, &input.reserved_free_time);
+    calendar.add_hours_spent(&input.hours_spent);
+    if let Some(now) = input.now {
+        calendar.make_hours_before_unavailable(now, &mut goal_map);
+    }
 
     dbg!(&calendar); //before tasks_completed_today
     add_tasks_completed_today(
         &calendar,
         &goal_map,
-        &tasks_completed_today,
+        &input.tasks_completed_today,
         &mut activities,
     );
     place(&mut calendar, &mut activities);
 
     dbg!(&calendar); //before simple
-    add_simple_activities(&mut calendar, &goal_map, &mut activities);
+    add_simple_activities(
+        &mut calendar,
+        &goal_map,
+        &input.hours_spent,
+        &mut activities,
+    );
+    add_missed_activities(&calendar, &goal_map, &input.missed_tasks, &mut activities);
     add_budget_min_day_activities(&mut calendar, &goal_map, &mut activities);
     //Todo: do we need a different treatment of Activities if they are part of budget or not ?
     //If not, simplify the code! Also for generating activities?
@@ -136,6 +170,10 @@ pub fn run_scheduler(
     add_budget_min_week_activities(&calendar, &goal_map, &mut activities);
     place(&mut calendar, &mut activities);
 
+    dbg!(&calendar); //before soft deadlines - before top-ups, so these don't take the hours after the deadline
+    allow_soft_deadline_activities_to_be_late(&mut calendar, &goal_map, &mut activities);
+    place(&mut calendar, &mut activities);
+
     dbg!(&calendar); //before get_budget_top_up_week
     add_budget_top_up_week_activities(&calendar, &goal_map, &mut activities);
     place(&mut calendar, &mut activities);
@@ -150,6 +188,6 @@ pub fn run_scheduler(
     //          => TODO: order activities before placing?
     dbg!(&calendar); //final result
 
-    calendar.log_impossible_activities(&activities);
-    calendar.print_new(&activities)
+    calendar.log_impossible_activities(&activities, &goal_map);
+    calendar.print_new(&activities, &goal_map, input.free_time_segments)
 }
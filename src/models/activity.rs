@@ -2,8 +2,8 @@ use std::cmp::{max, min};
 use std::vec;
 use std::{fmt, ops::Add};
 
-use chrono::{Duration, NaiveDateTime};
-use serde::Deserialize;
+use chrono::{Datelike, Duration, NaiveDateTime, Timelike};
+use serde::{Deserialize, Serialize};
 
 use crate::models::activity::ActivityStatus::Impossible;
 use crate::models::budget::TimeBudget;
@@ -11,8 +11,11 @@ use crate::models::calendar_interval::CalIntStatus;
 use crate::models::interval::Interval;
 use crate::services::interval_helper;
 
-use super::goal::Goal;
-use super::{calendar::Calendar, goal::Filter};
+use super::goal::{DeadlineMode, Distribution, Goal, Placement};
+use super::{
+    calendar::{Calendar, DeferredHours},
+    goal::{Filter, TimeWindow},
+};
 
 #[derive(Clone)]
 pub struct Activity {
@@ -29,6 +32,13 @@ pub struct Activity {
     pub compatible_intervals: Vec<Interval>,
     pub incompatible_intervals: Vec<Interval>,
     pub flex: Option<usize>,
+    pub completed_today: bool,
+    pub is_best_effort: bool,
+    pub preferred_windows: Vec<TimeWindow>,
+    pub placement: Placement,
+    pub carried_over: bool,
+    /// Activities that can be late make way for those that can't
+    pub has_soft_deadline: bool,
 }
 impl Activity {
     pub(crate) fn reset_compatible_intervals(&mut self) {
@@ -143,6 +153,50 @@ impl Activity {
             self.flex = None;
         }
     }
+
+    /// Cut the compatible intervals at midnight, so no block runs over into the next day
+    pub(crate) fn split_compatible_intervals_at_days(&mut self) {
+        let mut result: Vec<Interval> = Vec::with_capacity(self.compatible_intervals.len());
+        for own_interval in &self.compatible_intervals {
+            let mut start = own_interval.start;
+            while start < own_interval.end {
+                let end = min((start / 24 + 1) * 24, own_interval.end);
+                if end - start >= self.min_block_size {
+                    result.push(Interval { start, end });
+                } else {
+                    self.incompatible_intervals.push(Interval { start, end });
+                }
+                start = end;
+            }
+        }
+        if result.len() != self.compatible_intervals.len() {
+            self.flex = None;
+        }
+        self.compatible_intervals = result;
+    }
+}
+
+impl Activity {
+    /// Hours from start to end outside the preferred windows - always 0 without preferred windows
+    pub(crate) fn hours_outside_preference(
+        &self,
+        calendar: &Calendar,
+        start: usize,
+        end: usize,
+    ) -> usize {
+        if self.preferred_windows.is_empty() {
+            return 0;
+        }
+        (start..end)
+            .filter(|index| {
+                let hour_of_day = calendar.get_datetime_of(*index).hour() as usize;
+                !self
+                    .preferred_windows
+                    .iter()
+                    .any(|window| window.contains_hour(hour_of_day))
+            })
+            .count()
+    }
 }
 
 impl Activity {
@@ -184,6 +238,7 @@ impl Activity {
         goal: &Goal,
         calendar: &mut Calendar,
         duration_of_children: usize,
+        hours_spent: usize,
     ) -> Vec<Activity> {
         let (adjusted_goal_start, adjusted_goal_deadline) = goal.get_adj_start_deadline(calendar);
         let mut activities: Vec<Activity> = Vec::with_capacity(1);
@@ -200,32 +255,6 @@ impl Activity {
                     );
                 }
             }
-            let mut min_block_size = activity_total_duration;
-            if activity_total_duration > 8 {
-                min_block_size = 1;
-            };
-
-            let mut filters_option: Option<&Filter> = goal.filters.as_ref();
-            if calendar.is_participating_in_a_budget(&goal.id) {
-                filters_option = calendar.get_filters_for(&goal.id);
-            }
-
-            let mut adjusted_activity_deadline =
-                adjusted_goal_deadline.unwrap_or(calendar.end_date_time); //regular case
-            if goal.deadline.is_none() && !calendar.is_participating_in_a_budget(&goal.id) {
-                //special case for simple goals without a deadline
-                //they are allowed to be scheduled on the 'edge', crossing the calendar week boundary
-                adjusted_activity_deadline = adjusted_goal_deadline
-                    .unwrap_or(calendar.end_date_time.add(Duration::hours(24)));
-            };
-
-            let compatible_intervals: Vec<Interval> = interval_helper::get_compatible_intervals(
-                calendar,
-                filters_option,
-                adjusted_goal_start,
-                adjusted_activity_deadline,
-                &goal.not_on.clone(),
-            );
 
             let mut already_placed_for_goal_id: usize = 0;
             for cal_interval in &calendar.intervals {
@@ -240,11 +269,46 @@ impl Activity {
                 }
             }
 
+            //hours spent before the calendar are done as well
+            already_placed_for_goal_id += hours_spent;
+
             if already_placed_for_goal_id >= activity_total_duration {
                 return vec![];
             }
             activity_total_duration -= already_placed_for_goal_id;
 
+            //blocks are based on the hours still to do
+            let min_block_size = get_min_block_size(activity_total_duration, goal.max_per_day);
+
+            let budget_filters = calendar.get_filters_for(&goal.id);
+            let filters_option: Option<&Filter> = budget_filters.as_ref().or(goal.filters.as_ref());
+
+            //the calendar has one day after its end, a later deadline can't use more than that
+            let mut adjusted_activity_deadline = min(
+                adjusted_goal_deadline.unwrap_or(calendar.end_date_time),
+                calendar.end_date_time.add(Duration::hours(24)),
+            ); //regular case
+            if goal.deadline.is_none() && !calendar.is_participating_in_a_budget(&goal.id) {
+                //special case for simple goals without a deadline
+                //they are allowed to be scheduled on the 'edge', crossing the calendar week boundary
+                adjusted_activity_deadline = adjusted_goal_deadline
+                    .unwrap_or(calendar.end_date_time.add(Duration::hours(24)));
+            };
+
+            let mut compatible_intervals: Vec<Interval> = interval_helper::get_compatible_intervals(
+                calendar,
+                filters_option,
+                adjusted_goal_start,
+                adjusted_activity_deadline,
+                &goal.not_on.clone(),
+            );
+            //intervals too short to fit a block can never be used
+            //a distribution splits the hours over the days first, and then looks at the blocks of each day
+            if goal.distribution.is_none() {
+                compatible_intervals
+                    .retain(|interval| interval.end - interval.start >= min_block_size);
+            }
+
             dbg!(&compatible_intervals);
             let activity = Activity {
                 goal_id: goal.id.clone(),
@@ -260,33 +324,267 @@ impl Activity {
                 compatible_intervals,
                 incompatible_intervals: vec![],
                 flex: None,
+                completed_today: false,
+                is_best_effort: false,
+                preferred_windows: goal.preferred_windows.clone(),
+                placement: goal.placement,
+                carried_over: false,
+                has_soft_deadline: goal.deadline_mode == DeadlineMode::Soft,
             };
             dbg!(&activity);
+            if let Some(distribution) = goal.distribution {
+                //days after the calendar up to the deadline get their share in a later schedule
+                let mut capacities_after_calendar: Vec<usize> = vec![];
+                if let Some(deadline) = goal.deadline {
+                    let mut day_start = calendar.end_date_time;
+                    while day_start < deadline {
+                        let weekday = day_start.weekday();
+                        let hours_in_filter = filters_option.map_or(24, |filter| {
+                            if filter.on_days.contains(&weekday) {
+                                filter.hours_on(weekday)
+                            } else {
+                                0
+                            }
+                        });
+                        capacities_after_calendar
+                            .push(min(hours_in_filter, goal.max_per_day.unwrap_or(24)));
+                        day_start = day_start.add(Duration::days(1));
+                    }
+                }
+                return activity.distribute_over_days(
+                    calendar,
+                    distribution,
+                    &capacities_after_calendar,
+                );
+            }
             activities.push(activity);
         }
 
         activities
     }
 
+    /// Splits a simple activity into an activity for each calendar day, with the hours the distribution
+    /// gives that day. The hours of days after the calendar are reported as deferred.
+    /// Hours that don't fit in any day go to an activity over all days, to be placed where they still can.
+    fn distribute_over_days(
+        self,
+        calendar: &mut Calendar,
+        distribution: Distribution,
+        capacities_after_calendar: &[usize],
+    ) -> Vec<Activity> {
+        let days: Vec<Interval> = (24..calendar.hours() - 24)
+            .step_by(24)
+            .map(|day_start| Interval {
+                start: day_start,
+                end: day_start + 24,
+            })
+            .collect();
+        let intervals_per_day: Vec<Vec<Interval>> = days
+            .iter()
+            .map(|day| {
+                self.compatible_intervals
+                    .iter()
+                    .filter(|interval| interval.start < day.end && interval.end > day.start)
+                    .map(|interval| Interval {
+                        start: max(interval.start, day.start),
+                        end: min(interval.end, day.end),
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut capacities: Vec<usize> = days
+            .iter()
+            .zip(&intervals_per_day)
+            .map(|(day, intervals)| {
+                let hours: usize = intervals
+                    .iter()
+                    .map(|interval| interval.end - interval.start)
+                    .sum();
+                min(
+                    hours,
+                    calendar.get_hours_left_by_max_per_day(&self.goal_id, day.start),
+                )
+            })
+            .collect();
+        capacities.extend(capacities_after_calendar);
+        let hours_per_day = get_hours_per_day(&capacities, self.total_duration, distribution);
+
+        //hours given to days after the calendar are left for a later schedule, but reported
+        let hours_after_calendar: usize = hours_per_day[days.len()..].iter().sum();
+        if hours_after_calendar > 0 {
+            calendar.deferred_hours.push(DeferredHours {
+                id: self.goal_id.clone(),
+                hours: hours_after_calendar,
+                period_start_date_time: calendar.end_date_time,
+                period_end_date_time: self.deadline,
+            });
+        }
+        //hours given to days after the calendar aren't left over
+        let hours_left = self.total_duration - hours_per_day.iter().sum::<usize>();
+
+        //the days after the calendar already got their share, left over hours stay in the calendar
+        let intervals_in_calendar: Vec<Interval> = intervals_per_day.concat();
+        let mut activities: Vec<Activity> = vec![];
+        for ((day, mut intervals), hours) in days.iter().zip(intervals_per_day).zip(hours_per_day) {
+            if hours == 0 {
+                continue;
+            }
+            //the hours of a day are one block if they fit in one
+            let mut min_block_size = hours;
+            if hours > 8
+                || !intervals
+                    .iter()
+                    .any(|interval| interval.end - interval.start >= hours)
+            {
+                min_block_size = 1;
+            }
+            intervals.retain(|interval| interval.end - interval.start >= min_block_size);
+            activities.push(Activity {
+                min_block_size,
+                max_block_size: min_block_size,
+                total_duration: hours,
+                duration_left: hours,
+                start: max(self.start, calendar.get_datetime_of(day.start)),
+                compatible_intervals: intervals,
+                ..self.clone()
+            });
+        }
+        if hours_left > 0 {
+            activities.push(Activity {
+                min_block_size: 1,
+                max_block_size: 1,
+                total_duration: hours_left,
+                duration_left: hours_left,
+                compatible_intervals: intervals_in_calendar,
+                ..self
+            });
+        }
+        activities
+    }
+
+    /// An activity to place the hours of a missed task again, with the same constraints as the goal
+    pub(crate) fn get_missed_activities(
+        goal: &Goal,
+        calendar: &Calendar,
+        hours_missed: usize,
+    ) -> Vec<Activity> {
+        if hours_missed == 0 {
+            return vec![];
+        }
+        let (mut adjusted_goal_start, adjusted_goal_deadline) =
+            goal.get_adj_start_deadline(calendar);
+        //a goal crossing midnight runs over the edges of the calendar - hours there aren't in the output
+        adjusted_goal_start = max(adjusted_goal_start, calendar.start_date_time);
+        let adjusted_goal_deadline = min(
+            adjusted_goal_deadline.unwrap_or(calendar.end_date_time),
+            calendar.end_date_time,
+        );
+        let min_block_size = get_min_block_size(hours_missed, goal.max_per_day);
+
+        let budget_filters = calendar.get_filters_for(&goal.id);
+        let filters_option: Option<&Filter> = budget_filters.as_ref().or(goal.filters.as_ref());
+
+        let mut compatible_intervals: Vec<Interval> = interval_helper::get_compatible_intervals(
+            calendar,
+            filters_option,
+            adjusted_goal_start,
+            adjusted_goal_deadline,
+            &goal.not_on,
+        );
+        compatible_intervals.retain(|interval| interval.end - interval.start >= min_block_size);
+
+        vec![Activity {
+            goal_id: goal.id.clone(),
+            activity_type: ActivityType::SimpleGoal,
+            title: goal.title.clone(),
+            min_block_size,
+            max_block_size: min_block_size,
+            total_duration: hours_missed,
+            duration_left: hours_missed,
+            status: ActivityStatus::Unprocessed,
+            start: adjusted_goal_start,
+            deadline: goal.deadline,
+            compatible_intervals,
+            incompatible_intervals: vec![],
+            flex: None,
+            completed_today: false,
+            is_best_effort: false,
+            preferred_windows: goal.preferred_windows.clone(),
+            placement: goal.placement,
+            carried_over: true,
+            has_soft_deadline: goal.deadline_mode == DeadlineMode::Soft,
+        }]
+    }
+
+    /// The compatible intervals of a simple activity after its deadline, up to the end of the calendar
+    pub(crate) fn get_compatible_intervals_after_deadline(
+        &self,
+        goal: &Goal,
+        calendar: &Calendar,
+        deadline: NaiveDateTime,
+    ) -> Vec<Interval> {
+        let budget_filters = calendar.get_filters_for(&goal.id);
+        let filters_option: Option<&Filter> = budget_filters.as_ref().or(goal.filters.as_ref());
+        let mut compatible_intervals = interval_helper::get_compatible_intervals(
+            calendar,
+            filters_option,
+            deadline,
+            calendar.end_date_time,
+            &goal.not_on,
+        );
+        compatible_intervals
+            .retain(|interval| interval.end - interval.start >= self.min_block_size);
+        compatible_intervals
+    }
+
     pub(crate) fn get_activities_to_get_to_min_day_budget(
         goal_to_use: &Goal,
         calendar: &Calendar,
         time_budget: &TimeBudget,
+        hours_from_inner_budgets: usize,
     ) -> Vec<Activity> {
         let mut activities: Vec<Activity> = Vec::with_capacity(1);
-        let adjusted_goal_start = calendar.get_datetime_of(time_budget.calendar_start_index);
-        let adjusted_goal_deadline = calendar.get_datetime_of(time_budget.calendar_end_index);
-
-        let hours_to_schedule = time_budget.min_scheduled - time_budget.scheduled;
+        //a day crossing midnight runs over the edges of the calendar - hours there aren't in the output
+        let day_start = calendar.get_datetime_of(time_budget.calendar_start_index);
+        let day_end = calendar.get_datetime_of(time_budget.calendar_end_index);
+        //nor hours before the start or after the deadline of the goal
+        let adjusted_goal_start = max(max(day_start, calendar.start_date_time), goal_to_use.start);
+        let adjusted_goal_deadline = min(
+            min(day_end, calendar.end_date_time),
+            goal_to_use.deadline.unwrap_or(day_end),
+        );
+        if adjusted_goal_start >= adjusted_goal_deadline {
+            return activities;
+        }
 
+        //a budget nested inside another one also has to stay inside the filters of the outer one
+        let budget_filters = calendar.get_filters_for(&goal_to_use.id);
         let compatible_intervals: Vec<Interval> = interval_helper::get_compatible_intervals(
             calendar,
-            goal_to_use.filters.as_ref(),
+            budget_filters.as_ref().or(goal_to_use.filters.as_ref()),
             adjusted_goal_start,
             adjusted_goal_deadline,
             &goal_to_use.not_on.clone(),
         );
 
+        //hours placed by budgets nested inside this one count toward it too
+        let mut hours_to_schedule = (time_budget.min_scheduled - time_budget.scheduled)
+            .saturating_sub(hours_from_inner_budgets);
+        if hours_to_schedule == 0 {
+            return activities;
+        }
+        if adjusted_goal_start != day_start || adjusted_goal_deadline != day_end {
+            //the part of the day inside the calendar can only get what fits in it
+            let hours_available: usize = compatible_intervals
+                .iter()
+                .map(|interval| interval.end - interval.start)
+                .sum();
+            hours_to_schedule = min(hours_to_schedule, hours_available);
+            if hours_to_schedule == 0 {
+                return activities;
+            }
+        }
+
         activities.push(Activity {
             goal_id: goal_to_use.id.clone(),
             activity_type: ActivityType::GetToMinDayBudget,
@@ -301,6 +599,12 @@ impl Activity {
             compatible_intervals,
             incompatible_intervals: vec![],
             flex: None,
+            completed_today: false,
+            is_best_effort: false,
+            preferred_windows: goal_to_use.preferred_windows.clone(),
+            placement: goal_to_use.placement,
+            carried_over: false,
+            has_soft_deadline: false,
         });
 
         activities
@@ -310,19 +614,44 @@ impl Activity {
         goal_to_use: &Goal,
         calendar: &Calendar,
         time_budget: &TimeBudget,
+        hours_from_inner_budgets: usize,
     ) -> Vec<Activity> {
         let mut activities: Vec<Activity> = vec![];
 
-        let (adjusted_goal_start, adjusted_goal_deadline) =
+        let (mut adjusted_goal_start, adjusted_goal_deadline) =
             goal_to_use.get_adj_start_deadline(calendar);
-        //The time_budget is of type day - so we need to find room left till max_scheduled for that day
-        let max_hours = time_budget.max_scheduled - time_budget.scheduled;
+        //a goal crossing midnight starts the evening before the calendar - hours there aren't in the output
+        adjusted_goal_start = max(adjusted_goal_start, calendar.start_date_time);
+        //only the hours in the week of the time_budget count toward it
+        adjusted_goal_start = max(
+            adjusted_goal_start,
+            calendar.get_datetime_of(time_budget.calendar_start_index),
+        );
+        let week_end = min(
+            min(
+                calendar.get_datetime_of(time_budget.calendar_end_index),
+                calendar.end_date_time,
+            ),
+            adjusted_goal_deadline.unwrap_or(calendar.end_date_time),
+        );
+        if adjusted_goal_start >= week_end {
+            return activities;
+        }
+        //The time_budget is of type week - so we need to find room left till max_scheduled for that week
+        //hours placed by budgets nested inside this one count toward it too
+        let max_hours = (time_budget.max_scheduled - time_budget.scheduled)
+            .saturating_sub(hours_from_inner_budgets);
+        if max_hours == 0 {
+            return activities;
+        }
 
+        //a budget nested inside another one also has to stay inside the filters of the outer one
+        let budget_filters = calendar.get_filters_for(&goal_to_use.id);
         let compatible_intervals: Vec<Interval> = interval_helper::get_compatible_intervals(
             calendar,
-            goal_to_use.filters.as_ref(),
+            budget_filters.as_ref().or(goal_to_use.filters.as_ref()),
             adjusted_goal_start,
-            adjusted_goal_start.add(Duration::days(7)),
+            week_end,
             &goal_to_use.not_on.clone(),
         );
 
@@ -340,6 +669,12 @@ impl Activity {
             compatible_intervals,
             incompatible_intervals: vec![],
             flex: None,
+            completed_today: false,
+            is_best_effort: false,
+            preferred_windows: goal_to_use.preferred_windows.clone(),
+            placement: goal_to_use.placement,
+            carried_over: false,
+            has_soft_deadline: false,
         });
 
         activities
@@ -362,16 +697,30 @@ impl Activity {
             return activities;
         }
 
-        let adjusted_start = calendar.start_date_time.add(Duration::hours(
-            time_budget.calendar_start_index as i64 - 24,
-        ));
-        let adjusted_end = calendar
-            .start_date_time
-            .add(Duration::hours(time_budget.calendar_start_index as i64));
+        let adjusted_start = max(
+            calendar.start_date_time.add(Duration::hours(
+                time_budget.calendar_start_index as i64 - 24,
+            )),
+            max(calendar.start_date_time, goal_to_use.start),
+        );
+        let adjusted_end = min(
+            calendar
+                .start_date_time
+                .add(Duration::hours(time_budget.calendar_start_index as i64)),
+            min(
+                calendar.end_date_time,
+                goal_to_use.deadline.unwrap_or(calendar.end_date_time),
+            ),
+        );
+        if adjusted_start >= adjusted_end {
+            return activities;
+        }
 
+        //a budget nested inside another one also has to stay inside the filters of the outer one
+        let budget_filters = calendar.get_filters_for(&goal_to_use.id);
         let compatible_intervals: Vec<Interval> = interval_helper::get_compatible_intervals(
             calendar,
-            goal_to_use.filters.as_ref(),
+            budget_filters.as_ref().or(goal_to_use.filters.as_ref()),
             adjusted_start,
             adjusted_end,
             &goal_to_use.not_on.clone(),
@@ -391,12 +740,67 @@ impl Activity {
             compatible_intervals,
             incompatible_intervals: vec![],
             flex: None,
+            completed_today: false,
+            is_best_effort: false,
+            preferred_windows: goal_to_use.preferred_windows.clone(),
+            placement: goal_to_use.placement,
+            carried_over: false,
+            has_soft_deadline: false,
         });
 
         activities
     }
 }
 
+/// All hours in one block, or blocks of one hour for more than 8 hours.
+/// With a max per day, blocks fit in a day and add up to the hours.
+fn get_min_block_size(hours: usize, max_per_day: Option<usize>) -> usize {
+    let mut min_block_size = hours;
+    if hours > 8 {
+        min_block_size = 1;
+    };
+    if let Some(max_per_day) = max_per_day {
+        min_block_size = (1..=min(min_block_size, max_per_day))
+            .rev()
+            .find(|block_size| hours.is_multiple_of(*block_size))
+            .unwrap_or(1);
+    }
+    min_block_size
+}
+
+/// Hours for each day, up to its capacity - fewer hours in total if the days can't take them all
+fn get_hours_per_day(capacities: &[usize], total: usize, distribution: Distribution) -> Vec<usize> {
+    let mut hours_per_day = vec![0; capacities.len()];
+    let mut days: Vec<usize> = (0..capacities.len()).collect();
+    if distribution == Distribution::BackLoad {
+        days.reverse();
+    }
+    let mut hours_left = total;
+    match distribution {
+        Distribution::Even => {
+            //an hour for each day in turn, starting with the first day
+            let mut is_hour_added = true;
+            while hours_left > 0 && is_hour_added {
+                is_hour_added = false;
+                for day in &days {
+                    if hours_left > 0 && hours_per_day[*day] < capacities[*day] {
+                        hours_per_day[*day] += 1;
+                        hours_left -= 1;
+                        is_hour_added = true;
+                    }
+                }
+            }
+        }
+        Distribution::FrontLoad | Distribution::BackLoad => {
+            for day in days {
+                hours_per_day[day] = min(capacities[day], hours_left);
+                hours_left -= hours_per_day[day];
+            }
+        }
+    }
+    hours_per_day
+}
+
 #[derive(Debug, PartialEq, Clone, Deserialize, Hash)]
 pub enum ActivityStatus {
     Unprocessed,
@@ -407,7 +811,7 @@ pub enum ActivityStatus {
     BestEffort,
 }
 
-#[derive(Clone, Debug, PartialEq, Hash)]
+#[derive(Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
 pub enum ActivityType {
     SimpleGoal,
     GetToMinDayBudget,
@@ -1,13 +1,11 @@
-use std::{
-    fmt::{Debug, Formatter},
-    ops::{Add, Sub},
-};
+use std::cmp::{max, min};
+use std::fmt::{Debug, Formatter};
 
 #[cfg(debug_assertions)]
 use chrono::NaiveTime;
-use chrono::{Datelike, Duration};
+use chrono::{Datelike, NaiveDateTime, Weekday};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use super::{
     calendar::Calendar,
@@ -20,25 +18,126 @@ pub struct Budget {
     pub participating_goals: Vec<String>,
     pub time_budgets: Vec<TimeBudget>,
     pub time_filters: Filter,
+    pub consistent_time: bool,
+    /// Hour of the day the first hours of a budget with consistent time went to
+    pub anchor_hour: Option<usize>,
 }
 impl Budget {
     pub fn reduce_for_(&mut self, goal: &str, cal_index: usize, cal_index_end: usize) {
         if self.participating_goals.contains(&goal.to_string()) {
-            let iterator = self.time_budgets.iter_mut().enumerate();
-            for (_, time_budget) in iterator {
-                for offset in 0..(cal_index_end - cal_index) {
-                    if cal_index + offset >= time_budget.calendar_start_index
-                        && cal_index + offset < time_budget.calendar_end_index
-                    {
-                        time_budget.scheduled += 1;
-                    }
-                }
+            reduce_time_budgets(&mut self.time_budgets, cal_index, cal_index_end);
+        }
+    }
+}
+
+/// Max hours per day and/or week for all goals with a tag, wherever they are in the goal tree
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct TagCap {
+    pub tag: String,
+    pub max_per_day: Option<usize>,
+    pub max_per_week: Option<usize>,
+}
+
+/// Counts the hours of the goals with a tag - and the goals below them - against the caps of the tag
+#[derive(Debug, Clone)]
+pub struct TagBudget {
+    pub tag: String,
+    pub participating_goals: Vec<String>,
+    pub time_budgets: Vec<TimeBudget>,
+}
+
+impl TagBudget {
+    pub fn reduce_for_(&mut self, goal: &str, cal_index: usize, cal_index_end: usize) {
+        if self.participating_goals.contains(&goal.to_string()) {
+            reduce_time_budgets(&mut self.time_budgets, cal_index, cal_index_end);
+        }
+    }
+}
+
+/// Counts the hours of a goal without a budget against its max per day
+#[derive(Debug, Clone)]
+pub struct GoalCap {
+    pub goal_id: String,
+    pub time_budgets: Vec<TimeBudget>,
+}
+
+impl GoalCap {
+    pub fn reduce_for_(&mut self, goal: &str, cal_index: usize, cal_index_end: usize) {
+        if self.goal_id == goal {
+            reduce_time_budgets(&mut self.time_budgets, cal_index, cal_index_end);
+        }
+    }
+}
+
+/// The day the weeks of budgets and tag caps start on.
+/// Without one, weeks start on the start date of the calendar.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum WeekStart {
+    Monday,
+    Sunday,
+    /// Alias of Monday (ISO 8601 weeks start on Monday), accepted because the frontend sends
+    /// the week start setting as monday, sunday or iso
+    Iso,
+}
+
+impl WeekStart {
+    pub fn first_day(&self) -> Weekday {
+        match self {
+            WeekStart::Monday | WeekStart::Iso => Weekday::Mon,
+            WeekStart::Sunday => Weekday::Sun,
+        }
+    }
+}
+
+/// Hours to keep free between after_time and before_time of every day, whatever goals there are
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct ReservedFreeTime {
+    pub after_time: usize,
+    pub before_time: usize,
+    pub hours: usize,
+}
+
+/// A limit on the hours of all goals together
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum WorkloadLimit {
+    MaxHoursPerDay,
+    ReservedFreeTime,
+}
+
+/// Counts the hours of all goals against a workload limit
+#[derive(Debug, Clone)]
+pub struct WorkloadBudget {
+    pub limit: WorkloadLimit,
+    pub time_budget: TimeBudget,
+}
+
+impl WorkloadBudget {
+    pub fn reduce_for_(&mut self, cal_index: usize, cal_index_end: usize) {
+        reduce_time_budgets(
+            std::slice::from_mut(&mut self.time_budget),
+            cal_index,
+            cal_index_end,
+        );
+    }
+}
+
+fn reduce_time_budgets(time_budgets: &mut [TimeBudget], cal_index: usize, cal_index_end: usize) {
+    for time_budget in time_budgets {
+        for offset in 0..(cal_index_end - cal_index) {
+            if cal_index + offset >= time_budget.calendar_start_index
+                && cal_index + offset < time_budget.calendar_end_index
+            {
+                time_budget.scheduled += 1;
             }
         }
     }
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum TimeBudgetType {
     Day,
     Week,
@@ -52,19 +151,49 @@ pub struct TimeBudget {
     pub scheduled: usize,
     pub min_scheduled: usize,
     pub max_scheduled: usize,
+    /// Hours spent before the calendar, already included in scheduled
+    #[serde(default)]
+    pub spent: usize,
+    /// For a partial week: the min per week that its min is prorated from
+    #[serde(default)]
+    pub min_of_full_week: Option<usize>,
+}
+
+impl TimeBudget {
+    pub fn is_partial_week(&self) -> bool {
+        self.min_of_full_week.is_some()
+    }
+}
+
+/// The max of the day time_budgets ending in a period - a day crossing midnight goes with the day it ends on
+pub(crate) fn get_max_of_days_in(
+    time_budgets: &[TimeBudget],
+    cal_index_start: usize,
+    cal_index_end: usize,
+) -> usize {
+    time_budgets
+        .iter()
+        .filter(|time_budget| {
+            time_budget.time_budget_type == TimeBudgetType::Day
+                && time_budget.calendar_end_index > cal_index_start
+                && time_budget.calendar_end_index <= cal_index_end
+        })
+        .map(|time_budget| time_budget.max_scheduled)
+        .sum()
 }
 
 impl Debug for TimeBudget {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         write!(
             f,
-            "\n{:?} budget from index {:?}-{:?}: Scheduled {:?} / {:?}-{:?}\n",
-            &self.time_budget_type,
-            &self.calendar_start_index,
-            &self.calendar_end_index,
-            &self.scheduled,
-            &self.min_scheduled,
-            &self.max_scheduled
+            "\n{:?} budget from index {:?}-{:?}: Scheduled {:?} (spent {:?}) / {:?}-{:?}\n",
+            self.time_budget_type,
+            self.calendar_start_index,
+            self.calendar_end_index,
+            self.scheduled,
+            self.spent,
+            self.min_scheduled,
+            self.max_scheduled
         )
     }
 }
@@ -72,68 +201,193 @@ impl Debug for TimeBudget {
 pub fn get_time_budgets_from(calendar: &Calendar, goal: &Goal) -> Vec<TimeBudget> {
     println!("Getting time budgets from goal {}", goal.title);
 
-    let is_adjusted_day_start =
-        goal.filters.as_ref().unwrap().after_time > goal.filters.as_ref().unwrap().before_time;
+    let filter = goal.filters.as_ref().unwrap();
+    //a day of a budget can start later than midnight, see Filter::get_day_start_hour
+    let get_day_start_index = |day: usize| {
+        let weekday = calendar.get_datetime_of(day * 24).weekday();
+        day * 24 + filter.get_day_start_hour(weekday)
+    };
 
-    let mut day_start_offset = 0;
-    let mut budget_end_index = calendar.hours() - 24;
-    if is_adjusted_day_start {
-        budget_end_index += 24; //add an extra day
-        day_start_offset = 24 - goal.filters.as_ref().unwrap().after_time;
-    }
+    //hours of the budget before the start or after the deadline of its goal don't count
+    let goal_end = goal.deadline.unwrap_or(NaiveDateTime::MAX);
+    let get_hours_in_windows = |start: NaiveDateTime, end: NaiveDateTime| {
+        let (start, end) = (max(start, goal.start), min(end, goal_end));
+        if start < end {
+            filter.hours_between(start, end)
+        } else {
+            0
+        }
+    };
 
     let mut time_budgets: Vec<TimeBudget> = vec![];
-    //get a time_budget for each day
-    for hour_index in (24 - day_start_offset..budget_end_index - day_start_offset).step_by(24) {
-        println!("Day boundary assumed at hour_index {:?}", &hour_index);
+    //get a time_budget for each day that overlaps the calendar
+    for day in 0..calendar.hours() / 24 - 1 {
+        let start_index = get_day_start_index(day);
+        let end_index = get_day_start_index(day + 1);
+        if end_index <= 24 || start_index >= calendar.hours() - 24 {
+            continue;
+        }
+        println!("Day boundary assumed at hour_index {:?}", start_index);
         if let Some(config) = &goal.budget_config {
-            let mut min = config.min_per_day;
-            let mut max = config.max_per_day;
-            if let Some(filters) = &goal.filters {
-                if filters.on_days.contains(
-                    &calendar
-                        .start_date_time
-                        .sub(Duration::hours(24))
-                        .add(Duration::hours(hour_index as i64))
-                        .weekday(),
-                ) {
-                    //OK
-                } else {
-                    min = 0;
-                    max = 0;
-                }
+            let weekday = calendar.get_datetime_of(day * 24).weekday();
+            let mut min = config.min_on(weekday);
+            let mut max = config.max_on(weekday);
+            if filter.on_days.contains(&weekday) {
+                //a day can't get more hours than its windows allow
+                let hours_in_windows = get_hours_in_windows(
+                    calendar.get_datetime_of(start_index),
+                    calendar.get_datetime_of(end_index),
+                );
+                min = std::cmp::min(min, hours_in_windows);
+                max = std::cmp::min(max, hours_in_windows);
+            } else {
+                min = 0;
+                max = 0;
             }
             time_budgets.push(TimeBudget {
                 time_budget_type: TimeBudgetType::Day,
-                calendar_start_index: hour_index,
-                calendar_end_index: hour_index + 24,
+                calendar_start_index: start_index,
+                calendar_end_index: end_index,
                 scheduled: 0,
                 min_scheduled: min,
                 max_scheduled: max,
+                spent: 0,
+                min_of_full_week: None,
             });
         }
     }
 
-    //get a time_budget for each week
-    for hour_index in (24..calendar.hours() - 24).step_by(24 * 7) {
-        println!("Week boundary at hour_index {:?}", &hour_index);
+    //get a time_budget for each week - the first and last week can be partial
+    for week in calendar.get_week_intervals() {
+        println!("Week boundary at hour_index {:?}", week.start);
         #[cfg(debug_assertions)]
         assert_eq!(
-            calendar.get_datetime_of(hour_index).time(),
+            calendar.get_datetime_of(week.start).time(),
             NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
             "Assumed week boundary should be at midnight mark."
         );
         if let Some(config) = &goal.budget_config {
+            let mut min = config.min_per_week;
+            let mut min_of_full_week = None;
+            let max_of_days = get_max_of_days_in(&time_budgets, week.start, week.end);
+            if week.end - week.start < 24 * 7 {
+                //a partial week only gets its share of the min per week - and no more than the max of its days
+                let days = (week.end - week.start) / 24;
+                min_of_full_week = Some(config.min_per_week);
+                min = std::cmp::min((config.min_per_week * days).div_ceil(7), max_of_days);
+            } else if goal.start > calendar.get_datetime_of(week.start)
+                || goal_end < calendar.get_datetime_of(week.end)
+            {
+                //a week cut by the start or deadline of the goal gets no more than the max of its days
+                min = std::cmp::min(min, max_of_days);
+            }
             time_budgets.push(TimeBudget {
                 time_budget_type: TimeBudgetType::Week,
-                calendar_start_index: hour_index,
-                calendar_end_index: hour_index + 24 * 7,
+                calendar_start_index: week.start,
+                calendar_end_index: week.end,
                 scheduled: 0,
-                min_scheduled: config.min_per_week,
+                min_scheduled: min,
                 max_scheduled: config.max_per_week,
+                spent: 0,
+                min_of_full_week,
             });
         }
     }
     dbg!(&time_budgets);
     time_budgets
 }
+
+/// Time budgets with only a max, one per calendar day from midnight
+pub fn get_day_time_budgets_with_max(calendar: &Calendar, max_per_day: usize) -> Vec<TimeBudget> {
+    (24..calendar.hours() - 24)
+        .step_by(24)
+        .map(|hour_index| TimeBudget {
+            time_budget_type: TimeBudgetType::Day,
+            calendar_start_index: hour_index,
+            calendar_end_index: hour_index + 24,
+            scheduled: 0,
+            min_scheduled: 0,
+            max_scheduled: max_per_day,
+            spent: 0,
+            min_of_full_week: None,
+        })
+        .collect()
+}
+
+/// Time budgets for the caps of a tag: one per calendar day from midnight, and one per calendar week
+pub fn get_time_budgets_from_tag_cap(calendar: &Calendar, tag_cap: &TagCap) -> Vec<TimeBudget> {
+    let mut time_budgets: Vec<TimeBudget> = vec![];
+    if let Some(max_per_day) = tag_cap.max_per_day {
+        time_budgets.extend(get_day_time_budgets_with_max(calendar, max_per_day));
+    }
+    if let Some(max_per_week) = tag_cap.max_per_week {
+        for week in calendar.get_week_intervals() {
+            time_budgets.push(TimeBudget {
+                time_budget_type: TimeBudgetType::Week,
+                calendar_start_index: week.start,
+                calendar_end_index: week.end,
+                scheduled: 0,
+                min_scheduled: 0,
+                max_scheduled: max_per_week,
+                spent: 0,
+                min_of_full_week: None,
+            });
+        }
+    }
+    time_budgets
+}
+
+/// Time budgets for the workload limits: the max hours of each calendar day from midnight,
+/// and the hours of each reserved window that can still be used - including the window
+/// starting the day before the calendar, as it can cross midnight into the first day.
+pub fn get_workload_budgets(
+    calendar: &Calendar,
+    max_hours_per_day: Option<usize>,
+    reserved_free_time: &[ReservedFreeTime],
+) -> Vec<WorkloadBudget> {
+    let mut workload_budgets: Vec<WorkloadBudget> = vec![];
+    if let Some(max_hours_per_day) = max_hours_per_day {
+        for hour_index in (24..calendar.hours() - 24).step_by(24) {
+            workload_budgets.push(WorkloadBudget {
+                limit: WorkloadLimit::MaxHoursPerDay,
+                time_budget: TimeBudget {
+                    time_budget_type: TimeBudgetType::Day,
+                    calendar_start_index: hour_index,
+                    calendar_end_index: hour_index + 24,
+                    scheduled: 0,
+                    min_scheduled: 0,
+                    max_scheduled: max_hours_per_day,
+                    spent: 0,
+                    min_of_full_week: None,
+                },
+            });
+        }
+    }
+    for reserved in reserved_free_time {
+        let window_hours = if reserved.after_time < reserved.before_time {
+            reserved.before_time - reserved.after_time
+        } else {
+            24 - reserved.after_time + reserved.before_time
+        };
+        for hour_index in (0..calendar.hours() - 24).step_by(24) {
+            let window_start = hour_index + reserved.after_time;
+            workload_budgets.push(WorkloadBudget {
+                limit: WorkloadLimit::ReservedFreeTime,
+                time_budget: TimeBudget {
+                    time_budget_type: TimeBudgetType::Day,
+                    calendar_start_index: window_start,
+                    calendar_end_index: std::cmp::min(
+                        window_start + window_hours,
+                        calendar.hours(),
+                    ),
+                    scheduled: 0,
+                    min_scheduled: 0,
+                    max_scheduled: window_hours.saturating_sub(reserved.hours),
+                    spent: 0,
+                    min_of_full_week: None,
+                },
+            });
+        }
+    }
+    workload_budgets
+}
@@ -1,23 +1,30 @@
 use std::cmp::{max, min, PartialEq};
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt::{Debug, Formatter};
 use std::ops::{Add, Sub};
 use std::rc::Rc;
 
-use chrono::{Datelike, Days, Duration, NaiveDateTime, Weekday};
+use chrono::{Datelike, Days, Duration, NaiveDateTime, Timelike, Weekday};
 use serde::{Deserialize, Serialize};
 
 use crate::models::activity::ActivityStatus::{BestEffort, Impossible, Scheduled};
-use crate::models::activity::ActivityType::TopUpWeekBudget;
+use crate::models::activity::ActivityType::{GetToMinDayBudget, TopUpWeekBudget};
 use crate::models::budget::TimeBudgetType::{Day, Week};
 use crate::models::calendar_interval::CalIntStatus::Claimable;
-use crate::models::calendar_interval::{CalIntStatus, CalendarInterval};
+use crate::models::calendar_interval::{CalIntStatus, CalendarInterval, CalendarIntervals};
 use crate::models::interval::Interval;
+use crate::services::interval_helper;
 
 use super::activity::{Activity, ActivityStatus};
-use super::budget::{get_time_budgets_from, Budget};
-use super::goal::Goal;
-use super::task::{DayTasks, FinalTasks, Task};
+use super::budget::{
+    get_day_time_budgets_with_max, get_max_of_days_in, get_time_budgets_from,
+    get_time_budgets_from_tag_cap, get_workload_budgets, Budget, GoalCap, ReservedFreeTime,
+    TagBudget, TagCap, TimeBudget, WeekStart, WorkloadBudget, WorkloadLimit,
+};
+use super::goal::{DayConstraint, DayRelation, Filter, Goal, Slot};
+use super::task::{
+    BudgetConflict, BudgetProgress, BudgetWindow, DayTasks, FinalTasks, HoursSpent, Task,
+};
 
 #[derive(Debug, PartialEq, Clone, Hash)]
 pub enum Hour {
@@ -36,14 +43,46 @@ pub struct ImpossibleActivity {
     pub hours_missing: usize,
     pub period_start_date_time: NaiveDateTime,
     pub period_end_date_time: Option<NaiveDateTime>,
+    /// Workload limits that were reached in the period, and can be why the hours didn't fit
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub limited_by: Vec<WorkloadLimit>,
+}
+
+/// Hours a distribution gives to the days after the calendar, left for a later schedule
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeferredHours {
+    pub id: String,
+    pub hours: usize,
+    pub period_start_date_time: NaiveDateTime,
+    pub period_end_date_time: Option<NaiveDateTime>,
+}
+
+/// Goals that could still use a free hour, and goals that have it in their not on
+#[derive(Debug, PartialEq, Default)]
+struct FreeTime {
+    claimable_by: BTreeSet<String>,
+    blocked_for: BTreeSet<String>,
+    /// Hours before now can't be used or blocked anymore
+    is_past: bool,
 }
 
 pub struct Calendar {
     pub start_date_time: NaiveDateTime,
     pub end_date_time: NaiveDateTime,
     pub impossible_activities: Vec<ImpossibleActivity>,
+    pub deferred_hours: Vec<DeferredHours>,
     pub budgets: Vec<Budget>,
-    pub intervals: Vec<CalendarInterval>,
+    pub tag_budgets: Vec<TagBudget>,
+    pub goal_caps: Vec<GoalCap>,
+    /// The day constraints of each goal, including those other goals have with it
+    pub day_constraints: BTreeMap<String, Vec<DayConstraint>>,
+    pub workload_budgets: Vec<WorkloadBudget>,
+    /// Day the weeks of budgets start on - None for weeks from the start date
+    pub week_start: Option<WeekStart>,
+    /// Index of the first hour after now - None without a now cut-off
+    pub now_index: Option<usize>,
+    pub intervals: CalendarIntervals,
     registered_act_index: usize,
 }
 
@@ -53,20 +92,46 @@ impl Calendar {
     }
 }
 
+impl Calendar {
+    /// The weeks of the calendar from midnight, starting on the week start.
+    /// The first and last week are partial if the calendar doesn't start or end on a week boundary.
+    pub(crate) fn get_week_intervals(&self) -> Vec<Interval> {
+        let calendar_end_index = self.hours() - 24;
+        let mut week_boundary = 24;
+        if let Some(week_start) = self.week_start {
+            let days_to_week_start = (7 + week_start.first_day().num_days_from_monday()
+                - self.start_date_time.weekday().num_days_from_monday())
+                % 7;
+            week_boundary += 24 * days_to_week_start as usize;
+        }
+        let mut weeks: Vec<Interval> = vec![];
+        if week_boundary > 24 {
+            weeks.push(Interval {
+                start: 24,
+                end: min(week_boundary, calendar_end_index),
+            });
+        }
+        while week_boundary < calendar_end_index {
+            weeks.push(Interval {
+                start: week_boundary,
+                end: min(week_boundary + 24 * 7, calendar_end_index),
+            });
+            week_boundary += 24 * 7;
+        }
+        weeks
+    }
+}
+
 impl Calendar {
     pub(crate) fn unregister(&mut self, interval: &Interval, act_index: usize) {
-        for cal_int in &mut self.intervals {
-            //occupied interval could be using multiple cal_ints
-            let is_overlapping =
-                interval.start < cal_int.interval.end && cal_int.interval.start < interval.end;
-            if is_overlapping {
-                match cal_int.status {
-                    Claimable(ref mut claims) => {
-                        claims.remove(&act_index);
-                    }
-                    CalIntStatus::Occupied(_, _) => {
-                        //do nothing, there is no claim to unregister
-                    }
+        //occupied interval could be using multiple cal_ints
+        for cal_int in self.intervals.overlapping_mut(interval.start, interval.end) {
+            match cal_int.status {
+                Claimable(ref mut claims) => {
+                    claims.remove(&act_index);
+                }
+                CalIntStatus::Occupied(_, _) => {
+                    //do nothing, there is no claim to unregister
                 }
             }
         }
@@ -105,6 +170,44 @@ impl Calendar {
         for budget in &mut self.budgets {
             budget.reduce_for_(goal_id, cal_index_start, cal_index_end);
         }
+        for tag_budget in &mut self.tag_budgets {
+            tag_budget.reduce_for_(goal_id, cal_index_start, cal_index_end);
+        }
+        for goal_cap in &mut self.goal_caps {
+            goal_cap.reduce_for_(goal_id, cal_index_start, cal_index_end);
+        }
+        for workload_budget in &mut self.workload_budgets {
+            workload_budget.reduce_for_(cal_index_start, cal_index_end);
+        }
+    }
+
+    /// The time budgets the hours of a goal count toward: those of its budgets, of its capped tags,
+    /// of its own max per day and of the workload limits
+    fn get_time_budgets_for(&self, goal_id: &String) -> Vec<&TimeBudget> {
+        let budgets = self
+            .budgets
+            .iter()
+            .filter(|budget| budget.participating_goals.contains(goal_id))
+            .flat_map(|budget| budget.time_budgets.iter());
+        let tag_budgets = self
+            .tag_budgets
+            .iter()
+            .filter(|tag_budget| tag_budget.participating_goals.contains(goal_id))
+            .flat_map(|tag_budget| tag_budget.time_budgets.iter());
+        let goal_caps = self
+            .goal_caps
+            .iter()
+            .filter(|goal_cap| &goal_cap.goal_id == goal_id)
+            .flat_map(|goal_cap| goal_cap.time_budgets.iter());
+        let workload_budgets = self
+            .workload_budgets
+            .iter()
+            .map(|workload_budget| &workload_budget.time_budget);
+        budgets
+            .chain(tag_budgets)
+            .chain(goal_caps)
+            .chain(workload_budgets)
+            .collect()
     }
 }
 
@@ -121,56 +224,63 @@ impl Calendar {
         }
 
         //check if max_week reached
-        for budget in &self.budgets {
-            if budget.participating_goals.contains(&activity.goal_id) {
-                for time_budget in &budget.time_budgets {
-                    if time_budget.time_budget_type == Week
-                        && time_budget.max_scheduled == time_budget.scheduled
-                    {
-                        activity.reset_compatible_intervals();
-                        return;
-                    }
-                    if time_budget.time_budget_type == Day
-                        && time_budget.max_scheduled == time_budget.scheduled
-                    {
-                        activity.remove_interval(&Interval {
-                            start: time_budget.calendar_start_index,
-                            end: time_budget.calendar_end_index,
-                        });
-                    }
-                }
+        let time_budgets = self.get_time_budgets_for(&activity.goal_id);
+        for time_budget in &time_budgets {
+            //hours completed today can already take a tag over its cap
+            if time_budget.time_budget_type == Week
+                && time_budget.scheduled >= time_budget.max_scheduled
+            {
+                //the buffer days around the calendar go with the first and last week
+                let calendar_end_index = self.hours() - 24;
+                activity.remove_interval(&Interval {
+                    start: if time_budget.calendar_start_index == 24 {
+                        0
+                    } else {
+                        time_budget.calendar_start_index
+                    },
+                    end: if time_budget.calendar_end_index >= calendar_end_index {
+                        self.hours()
+                    } else {
+                        time_budget.calendar_end_index
+                    },
+                });
+                continue;
+            }
+            if time_budget.time_budget_type == Day
+                && time_budget.scheduled >= time_budget.max_scheduled
+            {
+                activity.remove_interval(&Interval {
+                    start: time_budget.calendar_start_index,
+                    end: time_budget.calendar_end_index,
+                });
             }
         }
 
         let mut intervals_that_cant_fit_in_budget: Vec<Interval> = vec![];
         for act_int in &activity.compatible_intervals {
-            for hour_index in act_int.start..act_int.end - activity.min_block_size {
+            for hour_index in act_int.start..=act_int.end - activity.min_block_size {
                 //is this even useful? Can't we assume they are valid?
                 //does cutting leave invalid intervals?
-                for offset in 0..activity.min_block_size {
+                for offset in 1..=activity.min_block_size {
                     //cycle through all relevant budgets and check this position
-                    for budget in &self.budgets {
-                        if !budget.participating_goals.contains(&activity.goal_id) {
-                            continue; //budget not relevant for this activity
-                        }
-                        for time_budget in &budget.time_budgets {
-                            let overlap_with_budget_start =
-                                max(hour_index, time_budget.calendar_start_index);
-                            let overlap_with_budget_end =
-                                min(hour_index + offset, time_budget.calendar_end_index);
-                            let budget_left_for_budget_interval =
-                                time_budget.max_scheduled - time_budget.scheduled;
-                            if overlap_with_budget_end > overlap_with_budget_start
-                                && overlap_with_budget_end - overlap_with_budget_start
-                                    > budget_left_for_budget_interval
-                            {
-                                // invalid - so remove_int(only_first_hour_int)
-                                // as others might still be valid
-                                intervals_that_cant_fit_in_budget.push(Interval {
-                                    start: hour_index,
-                                    end: hour_index + 1,
-                                });
-                            }
+                    for time_budget in &time_budgets {
+                        let overlap_with_budget_start =
+                            max(hour_index, time_budget.calendar_start_index);
+                        let overlap_with_budget_end =
+                            min(hour_index + offset, time_budget.calendar_end_index);
+                        let budget_left_for_budget_interval = time_budget
+                            .max_scheduled
+                            .saturating_sub(time_budget.scheduled);
+                        if overlap_with_budget_end > overlap_with_budget_start
+                            && overlap_with_budget_end - overlap_with_budget_start
+                                > budget_left_for_budget_interval
+                        {
+                            // invalid - so remove_int(only_first_hour_int)
+                            // as others might still be valid
+                            intervals_that_cant_fit_in_budget.push(Interval {
+                                start: hour_index,
+                                end: hour_index + 1,
+                            });
                         }
                     }
                 }
@@ -214,34 +324,49 @@ impl Calendar {
         activities: &mut [Activity],
     ) {
         let mut impacted_act_indexes: HashSet<usize> = HashSet::new();
-        for cal_interval in &mut self.intervals {
-            let is_overlapping = interval.start < cal_interval.interval.end
-                && cal_interval.interval.start < interval.end;
-            if is_overlapping {
-                #[cfg(debug_assertions)]
-                assert!(
-                    cal_interval.interval.end <= interval.end && cal_interval.interval.start >= interval.start,
-                    "Assumption broken: If cal_interval and interval overlap, cal_interval should always be equal or subset of occupied interval."
-                );
+        for cal_interval in self.intervals.overlapping_mut(interval.start, interval.end) {
+            #[cfg(debug_assertions)]
+            assert!(
+                cal_interval.interval.end <= interval.end && cal_interval.interval.start >= interval.start,
+                "Assumption broken: If cal_interval and interval overlap, cal_interval should always be equal or subset of occupied interval."
+            );
 
-                if let Claimable(claims) = &mut cal_interval.status {
-                    for act_index_in_claim in claims.iter() {
-                        activities[*act_index_in_claim].remove_interval(interval); //will reset flex if incompatible intervals are generated
-                        if *act_index_in_claim != act_index {
-                            impacted_act_indexes.insert(*act_index_in_claim);
-                        }
+            if let Claimable(claims) = &mut cal_interval.status {
+                for act_index_in_claim in claims.iter() {
+                    activities[*act_index_in_claim].remove_interval(interval); //will reset flex if incompatible intervals are generated
+                    if *act_index_in_claim != act_index {
+                        impacted_act_indexes.insert(*act_index_in_claim);
                     }
                 }
-                cal_interval.status =
-                    CalIntStatus::Occupied(act_index, activities[act_index].goal_id.clone());
-            } else if let Claimable(claims) = &mut cal_interval.status {
-                if claims.contains(&act_index) && activities[act_index].status == Scheduled {
-                    claims.remove(&act_index);
-                    for act_index_in_claim in claims.iter() {
-                        activities[*act_index_in_claim].flex_reset();
+            }
+            cal_interval.status =
+                CalIntStatus::Occupied(act_index, activities[act_index].goal_id.clone());
+        }
+        if activities[act_index].status == Scheduled {
+            //let go of remaining claims - only its own intervals can have them,
+            //including incompatible ones that weren't unregistered yet
+            let own_intervals: Vec<Interval> = activities[act_index]
+                .compatible_intervals
+                .iter()
+                .chain(&activities[act_index].incompatible_intervals)
+                .cloned()
+                .collect();
+            for own_interval in &own_intervals {
+                for cal_interval in self
+                    .intervals
+                    .overlapping_mut(own_interval.start, own_interval.end)
+                {
+                    if let Claimable(claims) = &mut cal_interval.status {
+                        if claims.remove(&act_index) {
+                            for act_index_in_claim in claims.iter() {
+                                activities[*act_index_in_claim].flex_reset();
+                            }
+                        }
                     }
                 }
             }
+            activities[act_index].reset_compatible_intervals();
+            activities[act_index].incompatible_intervals = vec![];
         }
         for act_index_impacted in &impacted_act_indexes {
             for incompatible_int in &activities[*act_index_impacted].incompatible_intervals {
@@ -253,6 +378,228 @@ impl Calendar {
     }
 }
 
+impl Calendar {
+    /// Day constraints work both ways, so they are kept for both goals
+    pub fn add_day_constraints(&mut self, goal_map: &BTreeMap<String, Goal>) {
+        for goal in goal_map.values() {
+            for constraint in &goal.day_constraints {
+                //only staying off the next day makes sense for a goal and itself
+                if !goal_map.contains_key(&constraint.goalid)
+                    || (constraint.goalid == goal.id
+                        && constraint.relation != DayRelation::NotConsecutiveDays)
+                {
+                    continue;
+                }
+                self.day_constraints
+                    .entry(goal.id.clone())
+                    .or_default()
+                    .push(constraint.clone());
+                if constraint.goalid != goal.id {
+                    self.day_constraints
+                        .entry(constraint.goalid.clone())
+                        .or_default()
+                        .push(DayConstraint {
+                            goalid: goal.id.clone(),
+                            relation: constraint.relation,
+                        });
+                }
+            }
+        }
+    }
+
+    /// Days from midnight that the hours of an interval are on, by the index of their start
+    pub(crate) fn get_days_of(interval: &Interval) -> BTreeSet<usize> {
+        (interval.start / 24..=(interval.end - 1) / 24)
+            .map(|day| day * 24)
+            .collect()
+    }
+
+    /// The intervals that a relation rules out for a goal related to a goal with hours on some days
+    fn get_intervals_ruled_out(
+        &self,
+        relation: DayRelation,
+        days: &BTreeSet<usize>,
+    ) -> Vec<Interval> {
+        let mut intervals: Vec<Interval> = vec![];
+        match relation {
+            DayRelation::SameDay => {
+                let mut start = 0;
+                for day in days {
+                    if *day > start {
+                        intervals.push(Interval { start, end: *day });
+                    }
+                    start = day + 24;
+                }
+                if start < self.hours() {
+                    intervals.push(Interval {
+                        start,
+                        end: self.hours(),
+                    });
+                }
+            }
+            DayRelation::NotSameDay => {
+                for day in days {
+                    intervals.push(Interval {
+                        start: *day,
+                        end: day + 24,
+                    });
+                }
+            }
+            DayRelation::NotConsecutiveDays => {
+                for day in days {
+                    if *day >= 24 {
+                        intervals.push(Interval {
+                            start: day - 24,
+                            end: *day,
+                        });
+                    }
+                    if day + 24 < self.hours() {
+                        intervals.push(Interval {
+                            start: day + 24,
+                            end: min(day + 48, self.hours()),
+                        });
+                    }
+                }
+            }
+        }
+        intervals
+    }
+
+    /// The intervals the day constraints of a goal rule out, given the days the related goals are on so far
+    fn get_intervals_ruled_out_for(&self, goal_id: &str) -> Vec<Interval> {
+        let Some(constraints) = self.day_constraints.get(goal_id) else {
+            return vec![];
+        };
+        constraints
+            .iter()
+            .flat_map(|constraint| {
+                let days = self.get_occupied_days_of(&constraint.goalid);
+                if days.is_empty() {
+                    return vec![];
+                }
+                self.get_intervals_ruled_out(constraint.relation, &days)
+            })
+            .collect()
+    }
+
+    /// Days from midnight that the hours of a goal are on so far
+    fn get_occupied_days_of(&self, goal_id: &str) -> BTreeSet<usize> {
+        let mut days: BTreeSet<usize> = BTreeSet::new();
+        for cal_interval in &self.intervals {
+            if let CalIntStatus::Occupied(_, occupying_goal_id) = &cal_interval.status {
+                if occupying_goal_id == goal_id {
+                    days.extend(Calendar::get_days_of(&cal_interval.interval));
+                }
+            }
+        }
+        days
+    }
+
+    /// Removes intervals from the activities of a goal that still have to be placed,
+    /// and lets go of their claims there
+    fn remove_intervals_for(
+        &mut self,
+        goal_id: &str,
+        intervals: &[Interval],
+        activities: &mut [Activity],
+    ) {
+        for (act_index, activity) in activities.iter_mut().enumerate() {
+            if activity.goal_id != goal_id || !is_still_to_place(activity) {
+                continue;
+            }
+            for interval in intervals {
+                activity.remove_interval(interval);
+            }
+            for incompatible_int in &activity.incompatible_intervals {
+                self.register(incompatible_int, act_index);
+                self.unregister(incompatible_int, act_index);
+            }
+            activity.incompatible_intervals = vec![];
+        }
+    }
+
+    /// Removes the days ruled out by the day constraints of a goal that just got hours on some days
+    /// from the activities of the goals related to it
+    pub(crate) fn apply_day_constraints(
+        &mut self,
+        goal_id: &str,
+        days: &BTreeSet<usize>,
+        activities: &mut [Activity],
+    ) {
+        let Some(constraints) = self.day_constraints.get(goal_id).cloned() else {
+            return;
+        };
+        for constraint in constraints {
+            let intervals_ruled_out = match constraint.relation {
+                //sharing any one of the days is enough
+                DayRelation::SameDay => self.get_intervals_ruled_out(
+                    constraint.relation,
+                    &self.get_occupied_days_of(goal_id),
+                ),
+                _ => self.get_intervals_ruled_out(constraint.relation, days),
+            };
+            self.remove_intervals_for(&constraint.goalid, &intervals_ruled_out, activities);
+        }
+    }
+
+    /// Activities added after other goals already got hours have to keep to the day constraints as well.
+    /// Goals that have to share a day only keep the days the other goal can still get hours on,
+    /// and a goal that can't be on consecutive days with itself can't run over midnight either.
+    pub(crate) fn prepare_day_constraints(&mut self, activities: &mut [Activity]) {
+        for (act_index, activity) in activities.iter_mut().enumerate() {
+            let not_on_consecutive_days =
+                self.day_constraints
+                    .get(&activity.goal_id)
+                    .is_some_and(|constraints| {
+                        constraints.iter().any(|constraint| {
+                            constraint.goalid == activity.goal_id
+                                && constraint.relation == DayRelation::NotConsecutiveDays
+                        })
+                    });
+            if !not_on_consecutive_days || !is_still_to_place(activity) {
+                continue;
+            }
+            activity.split_compatible_intervals_at_days();
+            for incompatible_int in &activity.incompatible_intervals {
+                self.register(incompatible_int, act_index);
+                self.unregister(incompatible_int, act_index);
+            }
+            activity.incompatible_intervals = vec![];
+        }
+        for (goal_id, constraints) in self.day_constraints.clone() {
+            let days = self.get_occupied_days_of(&goal_id);
+            if !days.is_empty() {
+                self.apply_day_constraints(&goal_id, &days, activities);
+            }
+            for constraint in constraints {
+                if constraint.relation != DayRelation::SameDay {
+                    continue;
+                }
+                let mut possible_days = self.get_occupied_days_of(&constraint.goalid);
+                for activity in activities.iter() {
+                    if activity.goal_id == constraint.goalid && is_still_to_place(activity) {
+                        for interval in &activity.compatible_intervals {
+                            possible_days.extend(Calendar::get_days_of(interval));
+                        }
+                    }
+                }
+                if possible_days.is_empty() {
+                    continue;
+                }
+                let intervals_ruled_out =
+                    self.get_intervals_ruled_out(DayRelation::SameDay, &possible_days);
+                self.remove_intervals_for(&goal_id, &intervals_ruled_out, activities);
+            }
+        }
+    }
+}
+
+fn is_still_to_place(activity: &Activity) -> bool {
+    activity.status == ActivityStatus::Unprocessed
+        || activity.status == BestEffort
+        || activity.status == ActivityStatus::Postponed
+}
+
 impl PartialEq<Rc<Activity>> for Activity {
     fn eq(&self, other: &Rc<Activity>) -> bool {
         self.goal_id == other.goal_id
@@ -267,74 +614,15 @@ impl PartialEq for Activity {
 
 impl Calendar {
     pub(crate) fn register(&mut self, interval: &Interval, act_index: usize) {
-        let mut result = self.intervals.clone();
-        let mut inner_result = Vec::with_capacity(result.capacity());
-        'cal_interval_loop: for cal_interval in &mut result {
-            let is_overlapping = interval.start < cal_interval.interval.end
-                && cal_interval.interval.start < interval.end;
-            if !is_overlapping {
-                inner_result.push(cal_interval.clone());
-                continue 'cal_interval_loop;
-            }
-            // we have an overlap!
-            let empty_begin = interval.start > cal_interval.interval.start;
-            let empty_end = interval.end < cal_interval.interval.end;
-
-            if !empty_begin && !empty_end {
-                // total overlap
-                // println!(
-                //     "total overlap {}-{} while registering.",
-                //     interval.start, interval.end
-                // );
-                cal_interval.claim_by(act_index);
-                inner_result.push(cal_interval.clone());
-            }
-            if !empty_begin && empty_end {
-                //overlap begin
-                // println!(
-                //     "overlap begin {}-{} while registering.",
-                //     cal_interval.interval.start, interval.end
-                // );
-                inner_result.push(cal_interval.clone());
-                inner_result.last_mut().unwrap().interval.end = interval.end;
-                inner_result.last_mut().unwrap().claim_by(act_index);
-                //empty end
-                inner_result.push(cal_interval.clone());
-                inner_result.last_mut().unwrap().interval.start = interval.end;
-                //continue 'act_interval_loop; //since we reached end of overlap already
-            }
-            if empty_begin && !empty_end {
-                //empty begin
-                // println!(
-                //     "overlap end {}-{} while registering.",
-                //     interval.end, cal_interval.interval.end
-                // );
-                inner_result.push(cal_interval.clone());
-                inner_result.last_mut().unwrap().interval.end = interval.start;
-                //overlap end
-                inner_result.push(cal_interval.clone());
-                inner_result.last_mut().unwrap().interval.start = interval.start;
-                inner_result.last_mut().unwrap().claim_by(act_index);
-            }
-            if empty_begin && empty_end {
-                // println!(
-                //     "overlap middle {}-{} while registering.",
-                //     interval.start, interval.end
-                // );
-                //empty begin
-                inner_result.push(cal_interval.clone());
-                inner_result.last_mut().unwrap().interval.end = interval.start;
-                //overlap end
-                inner_result.push(cal_interval.clone());
-                inner_result.last_mut().unwrap().interval.start = interval.start;
-                inner_result.last_mut().unwrap().interval.end = interval.end;
-                inner_result.last_mut().unwrap().claim_by(act_index);
-                //empty end
-                inner_result.push(cal_interval.clone());
-                inner_result.last_mut().unwrap().interval.start = interval.end;
-            }
-        }
-        self.intervals = inner_result.clone();
+        if interval.start >= interval.end {
+            return;
+        }
+        //split off any part of the cal_ints at the edges that is not overlapping
+        self.intervals.split_at(interval.start);
+        self.intervals.split_at(interval.end);
+        for cal_interval in self.intervals.overlapping_mut(interval.start, interval.end) {
+            cal_interval.claim_by(act_index);
+        }
     }
 }
 
@@ -363,38 +651,41 @@ impl Calendar {
         let number_of_days = (end_date_time - start_date_time).num_days(); //Todo use this later to stop limiting compatible
         println!(
             "Calendar of {:?} days, from {:?} to {:?} - index 0/24-{:?}/{:?}",
-            &number_of_days,
-            &start_date_time,
-            &end_date_time,
+            number_of_days,
+            start_date_time,
+            end_date_time,
             (end_date_time - start_date_time).num_hours() + 24,
             (end_date_time - start_date_time).num_hours() + 48
         );
         let number_of_hours_for_extended_calendar = 48 + number_of_days as usize * 24; // 48 extra for one day of buffer at front and back
-        let intervals = vec![CalendarInterval {
+        let intervals = CalendarIntervals::new(vec![CalendarInterval {
             //fragment the first day already so it can be split off easily when printing calendar
             interval: Interval {
                 start: 0,
                 end: number_of_hours_for_extended_calendar,
             },
             status: Claimable(HashSet::new()),
-        }];
+        }]);
 
         Self {
             start_date_time,
             end_date_time,
             impossible_activities: vec![],
+            deferred_hours: vec![],
             budgets: vec![],
+            tag_budgets: vec![],
+            goal_caps: vec![],
+            day_constraints: BTreeMap::new(),
+            workload_budgets: vec![],
+            week_start: None,
+            now_index: None,
             intervals,
             registered_act_index: 0,
         }
     }
 
     pub(crate) fn hours(&self) -> usize {
-        self.intervals
-            .last()
-            .expect("when calling hours there should be at least one interval in calendar.")
-            .interval
-            .end
+        self.intervals.end()
     }
     pub fn get_week_day_of(&self, index_to_test: usize) -> Weekday {
         #[cfg(debug_assertions)]
@@ -410,6 +701,100 @@ impl Calendar {
         date_time_of_index_to_test.weekday()
     }
 
+    /// All goals participating in any of the budgets or capped tags the goal participates in,
+    /// and the goal itself if it has a max per day
+    pub(crate) fn get_goals_sharing_budget_with(&self, goal_id: &String) -> HashSet<String> {
+        let mut goals: HashSet<String> = HashSet::new();
+        for budget in &self.budgets {
+            if budget.participating_goals.contains(goal_id) {
+                goals.extend(budget.participating_goals.iter().cloned());
+            }
+        }
+        for tag_budget in &self.tag_budgets {
+            if tag_budget.participating_goals.contains(goal_id) {
+                goals.extend(tag_budget.participating_goals.iter().cloned());
+            }
+        }
+        if self
+            .goal_caps
+            .iter()
+            .any(|goal_cap| &goal_cap.goal_id == goal_id)
+        {
+            goals.insert(goal_id.clone());
+        }
+        goals
+    }
+
+    /// Whether placing hours of the goal counts toward any budget, tag cap, max per day or workload limit
+    pub(crate) fn has_time_budgets_for(&self, goal_id: &String) -> bool {
+        !self.get_time_budgets_for(goal_id).is_empty()
+    }
+
+    /// Min hours still to be placed in a day or week by the budgets nested directly inside a budget.
+    /// Those hours count toward the outer budget as well, so it doesn't need to place them itself.
+    /// Only days or weeks of the inner budgets that lie inside the one of the outer budget are counted.
+    pub(crate) fn get_min_hours_of_inner_budgets(
+        &self,
+        budget: &Budget,
+        period: &TimeBudget,
+    ) -> usize {
+        let inner_budgets: Vec<&Budget> = self
+            .budgets
+            .iter()
+            .filter(|inner| {
+                inner.originating_goal_id != budget.originating_goal_id
+                    && budget
+                        .participating_goals
+                        .contains(&inner.originating_goal_id)
+            })
+            .collect();
+        //a budget nested deeper already counts toward the inner budget it is in
+        let is_nested_in_other_inner = |inner: &Budget| {
+            inner_budgets.iter().any(|other| {
+                other.originating_goal_id != inner.originating_goal_id
+                    && other
+                        .participating_goals
+                        .contains(&inner.originating_goal_id)
+            })
+        };
+        inner_budgets
+            .iter()
+            .filter(|inner| !is_nested_in_other_inner(inner))
+            .flat_map(|inner| inner.time_budgets.iter())
+            .filter(|inner_period| {
+                inner_period.time_budget_type == period.time_budget_type
+                    && inner_period.calendar_start_index >= period.calendar_start_index
+                    && inner_period.calendar_end_index <= period.calendar_end_index
+            })
+            .map(|inner_period| {
+                inner_period
+                    .min_scheduled
+                    .saturating_sub(inner_period.scheduled)
+            })
+            .sum()
+    }
+
+    /// The hour of the day a goal in a budget with consistent time prefers, once the budget has one
+    pub(crate) fn get_anchor_hour_for(&self, goal_id: &String) -> Option<usize> {
+        self.budgets
+            .iter()
+            .filter(|budget| budget.consistent_time && budget.participating_goals.contains(goal_id))
+            .find_map(|budget| budget.anchor_hour)
+    }
+
+    /// The first hours placed for a budget with consistent time set the hour the other days prefer
+    pub(crate) fn set_anchor_hour_for(&mut self, goal_id: &String, cal_index: usize) {
+        let hour = self.get_datetime_of(cal_index).hour() as usize;
+        for budget in &mut self.budgets {
+            if budget.consistent_time
+                && budget.anchor_hour.is_none()
+                && budget.participating_goals.contains(goal_id)
+            {
+                budget.anchor_hour = Some(hour);
+            }
+        }
+    }
+
     pub fn is_participating_in_a_budget(&self, goal_id: &String) -> bool {
         for budget in &self.budgets {
             if budget.participating_goals.contains(goal_id) {
@@ -434,23 +819,187 @@ impl Calendar {
         // index
         (date_time.add(Duration::hours(24)) - self.start_date_time).num_hours() as usize
     }
-    pub fn print_new(&mut self, activities: &Vec<Activity>) -> FinalTasks {
+    pub fn print_new(
+        &mut self,
+        activities: &Vec<Activity>,
+        goal_map: &BTreeMap<String, Goal>,
+        free_time_segments: bool,
+    ) -> FinalTasks {
         println!("Printing new calendar:");
         dbg!(&self);
         println!("Now consolidating intervals and splitting on day boundaries...");
-        consolidate_intervals_on_goal_id(&mut self.intervals);
-        split_intervals_on_day_boundaries(&mut self.intervals);
-        dbg!(&self);
+        let mut intervals = self.intervals.to_vec();
+        let mut free_time: BTreeMap<usize, FreeTime> = BTreeMap::new();
+        if free_time_segments {
+            split_free_intervals_per_hour(&mut intervals);
+            free_time = self.get_free_time(activities, goal_map);
+        }
+        consolidate_intervals_on_goal_id(&mut intervals, activities, &free_time);
+        split_intervals_on_day_boundaries(&mut intervals);
+        dbg!(&intervals);
+        let mut parent_goal_ids: BTreeMap<String, String> = BTreeMap::new();
+        for goal in goal_map.values() {
+            for child in goal.children.iter().flatten() {
+                parent_goal_ids.insert(child.clone(), goal.id.clone());
+            }
+        }
         let mut scheduled: Vec<DayTasks> = transform_intervals_to_day_tasks(
-            self.intervals.clone(),
+            intervals,
             activities,
+            &self.budgets,
+            &parent_goal_ids,
+            &free_time,
             self.start_date_time,
         );
 
         FinalTasks {
             scheduled: scheduled.drain(1..scheduled.len() - 1).collect::<Vec<_>>(), //skip the first leading 24 hours, and last trailing 24 hours
             impossible: self.impossible_activities.clone(),
+            deferred: self.deferred_hours.clone(),
+            budgets: self.get_budget_progress(),
+            budget_conflicts: self.get_budget_conflicts(),
+        }
+    }
+
+    /// Goals in more than one budget, without any hour inside the filters of all of them
+    fn get_budget_conflicts(&self) -> Vec<BudgetConflict> {
+        let mut goal_ids: BTreeSet<&String> = BTreeSet::new();
+        for budget in &self.budgets {
+            goal_ids.extend(budget.participating_goals.iter());
         }
+        goal_ids
+            .into_iter()
+            .filter_map(|goal_id| {
+                let budget_ids: Vec<String> = self
+                    .budgets
+                    .iter()
+                    .filter(|budget| budget.participating_goals.contains(goal_id))
+                    .map(|budget| budget.originating_goal_id.clone())
+                    .collect();
+                let has_hours_in_common = self
+                    .get_filters_for(goal_id)
+                    .is_some_and(|filter| !filter.on_days.is_empty());
+                if budget_ids.len() < 2 || has_hours_in_common {
+                    return None;
+                }
+                Some(BudgetConflict {
+                    goalid: goal_id.clone(),
+                    budget_ids,
+                })
+            })
+            .collect()
+    }
+
+    fn get_budget_progress(&self) -> Vec<BudgetProgress> {
+        self.budgets
+            .iter()
+            .map(|budget| BudgetProgress {
+                id: budget.originating_goal_id.clone(),
+                anchor_hour: budget.anchor_hour,
+                windows: budget
+                    .time_budgets
+                    .iter()
+                    .map(|time_budget| BudgetWindow {
+                        window_type: time_budget.time_budget_type.clone(),
+                        period_start_date_time: self
+                            .get_datetime_of(time_budget.calendar_start_index),
+                        period_end_date_time: self.get_datetime_of(time_budget.calendar_end_index),
+                        scheduled: time_budget.scheduled - time_budget.spent,
+                        min: time_budget.min_scheduled,
+                        max: time_budget.max_scheduled,
+                        spent: time_budget.spent,
+                        partial: time_budget.is_partial_week(),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Free time per free hour index
+    /// Goals in a budget can still use free time while the budget has room left for it,
+    /// other goals only while they are missing hours - and only where their tag caps, max per day,
+    /// workload limits and day constraints still allow it.
+    fn get_free_time(
+        &self,
+        activities: &[Activity],
+        goal_map: &BTreeMap<String, Goal>,
+    ) -> BTreeMap<usize, FreeTime> {
+        let mut free_time: BTreeMap<usize, FreeTime> = BTreeMap::new();
+        for cal_int in &self.intervals {
+            if let Claimable(_) = cal_int.status {
+                for hour_index in cal_int.interval.start..cal_int.interval.end {
+                    free_time.insert(hour_index, FreeTime::default());
+                }
+            }
+        }
+        for goal in goal_map.values() {
+            let budgets: Vec<&Budget> = self
+                .budgets
+                .iter()
+                .filter(|budget| budget.participating_goals.contains(&goal.id))
+                .collect();
+            let is_missing_hours = activities.iter().any(|activity| {
+                activity.goal_id == goal.id
+                    && activity.status != Scheduled
+                    && !activity.completed_today
+            });
+            if budgets.is_empty() && !is_missing_hours {
+                continue;
+            }
+            let (start, deadline) = goal.get_adj_start_deadline(self);
+            let budget_filter = self.get_filters_for(&goal.id);
+            let filter = budget_filter.as_ref().or(goal.filters.as_ref());
+            let compatible_intervals = interval_helper::get_compatible_intervals(
+                self,
+                filter,
+                start,
+                min(deadline.unwrap_or(self.end_date_time), self.end_date_time),
+                &goal.not_on,
+            );
+            let time_budgets = self.get_time_budgets_for(&goal.id);
+            let intervals_ruled_out = self.get_intervals_ruled_out_for(&goal.id);
+            for interval in compatible_intervals {
+                for (hour_index, hour) in free_time.range_mut(interval.start..interval.end) {
+                    let has_budget_room = time_budgets.iter().all(|time_budget| {
+                        *hour_index < time_budget.calendar_start_index
+                            || *hour_index >= time_budget.calendar_end_index
+                            || time_budget.scheduled < time_budget.max_scheduled
+                    });
+                    let is_ruled_out = intervals_ruled_out.iter().any(|interval| {
+                        *hour_index >= interval.start && *hour_index < interval.end
+                    });
+                    if has_budget_room && !is_ruled_out {
+                        hour.claimable_by.insert(goal.id.clone());
+                    }
+                }
+            }
+        }
+        let calendar_start = self.start_date_time.sub(Duration::days(1));
+        let calendar_end = self.end_date_time.add(Duration::days(1));
+        for goal in goal_map.values() {
+            for slot in goal.not_on.iter().flatten() {
+                let start = max(slot.start, calendar_start);
+                let end = min(slot.end, calendar_end);
+                if start >= end {
+                    continue;
+                }
+                for (_, hour) in
+                    free_time.range_mut(self.get_index_of(start)..self.get_index_of(end))
+                {
+                    hour.blocked_for.insert(goal.id.clone());
+                }
+            }
+        }
+        //the now cut-off puts hours before now in the not on of every goal - they are past, not blocked
+        if let Some(now_index) = self.now_index {
+            for (_, hour) in free_time.range_mut(..now_index) {
+                *hour = FreeTime {
+                    is_past: true,
+                    ..FreeTime::default()
+                };
+            }
+        }
+        free_time
     }
 
     pub fn add_budgets_from(&mut self, goal_map: &mut BTreeMap<String, Goal>) {
@@ -466,8 +1015,9 @@ impl Calendar {
                     //check 1
                     let mut min_per_day_sum = 0;
                     if let Some(filters) = &goal.filters {
-                        for _ in &filters.on_days {
-                            min_per_day_sum += budget_config.min_per_day;
+                        for weekday in &filters.on_days {
+                            min_per_day_sum +=
+                                min(budget_config.min_on(*weekday), filters.hours_on(*weekday));
                         }
                     }
                     assert!(
@@ -479,14 +1029,28 @@ impl Calendar {
                     );
 
                     //check 2
-                    #[cfg(debug_assertions)]
-                    assert!(
-                        budget_config.max_per_day <= budget_config.max_per_week,
-                        "max_per_day {:?} is higher than max_per_week {:?} for goal {:?}",
-                        budget_config.max_per_day,
-                        budget_config.max_per_week,
-                        goal.title
-                    );
+                    if let Some(filters) = &goal.filters {
+                        for weekday in &filters.on_days {
+                            assert!(
+                                budget_config.max_on(*weekday) <= budget_config.max_per_week,
+                                "max_per_day {:?} on {:?} is higher than max_per_week {:?} for goal {:?}",
+                                budget_config.max_on(*weekday),
+                                weekday,
+                                budget_config.max_per_week,
+                                goal.title
+                            );
+
+                            //check 3
+                            assert!(
+                                budget_config.min_on(*weekday) <= budget_config.max_on(*weekday),
+                                "min_per_day {:?} on {:?} is higher than max_per_day {:?} for goal {:?}",
+                                budget_config.min_on(*weekday),
+                                weekday,
+                                budget_config.max_on(*weekday),
+                                goal.title
+                            );
+                        }
+                    }
                 }
             }
             if let Some(_budget_config) = &goal.budget_config {
@@ -509,6 +1073,11 @@ impl Calendar {
                         participating_goals: descendants_added,
                         time_budgets: get_time_budgets_from(self, goal),
                         time_filters: goal.filters.clone().unwrap(),
+                        consistent_time: goal
+                            .budget_config
+                            .as_ref()
+                            .is_some_and(|config| config.consistent_time),
+                        anchor_hour: None,
                     });
                     continue;
                 }
@@ -523,6 +1092,11 @@ impl Calendar {
                             participating_goals: descendants_added,
                             time_budgets: get_time_budgets_from(self, goal),
                             time_filters: goal.filters.clone().unwrap(),
+                            consistent_time: goal
+                                .budget_config
+                                .as_ref()
+                                .is_some_and(|config| config.consistent_time),
+                            anchor_hour: None,
                         });
                         break;
                     }
@@ -539,7 +1113,179 @@ impl Calendar {
         }
     }
 
-    pub fn log_impossible_activities(&mut self, activities: &Vec<Activity>) {
+    /// A tag cap counts the hours of all goals with the tag, and of the goals below them
+    pub fn add_tag_caps(&mut self, tag_caps: &[TagCap], goal_map: &BTreeMap<String, Goal>) {
+        for tag_cap in tag_caps {
+            let mut participating_goals: Vec<String> = goal_map
+                .values()
+                .filter(|goal| goal.tags.contains(&tag_cap.tag))
+                .map(|goal| goal.id.clone())
+                .collect();
+            let mut index = 0;
+            while index < participating_goals.len() {
+                if let Some(children) = goal_map
+                    .get(&participating_goals[index])
+                    .and_then(|goal| goal.children.as_ref())
+                {
+                    for child in children {
+                        if !participating_goals.contains(child) {
+                            participating_goals.push(child.clone());
+                        }
+                    }
+                }
+                index += 1;
+            }
+            self.tag_budgets.push(TagBudget {
+                tag: tag_cap.tag.clone(),
+                participating_goals,
+                time_budgets: get_time_budgets_from_tag_cap(self, tag_cap),
+            });
+        }
+    }
+
+    /// Goals without a budget can have a max per day of their own
+    pub fn add_goal_caps(&mut self, goal_map: &BTreeMap<String, Goal>) {
+        for goal in goal_map.values() {
+            if goal.budget_config.is_some() {
+                continue;
+            }
+            if let Some(max_per_day) = goal.max_per_day {
+                self.goal_caps.push(GoalCap {
+                    goal_id: goal.id.clone(),
+                    time_budgets: get_day_time_budgets_with_max(self, max_per_day),
+                });
+            }
+        }
+    }
+
+    /// Hours a goal can still get on the calendar day starting at an index, by its max per day
+    pub(crate) fn get_hours_left_by_max_per_day(&self, goal_id: &str, day_index: usize) -> usize {
+        self.goal_caps
+            .iter()
+            .filter(|goal_cap| goal_cap.goal_id == goal_id)
+            .flat_map(|goal_cap| goal_cap.time_budgets.iter())
+            .filter(|time_budget| time_budget.calendar_start_index == day_index)
+            .map(|time_budget| {
+                time_budget
+                    .max_scheduled
+                    .saturating_sub(time_budget.scheduled)
+            })
+            .min()
+            .unwrap_or(usize::MAX)
+    }
+
+    /// The max hours per day and the reserved free time count the hours of all goals together
+    pub fn add_workload_limits(
+        &mut self,
+        max_hours_per_day: Option<usize>,
+        reserved_free_time: &[ReservedFreeTime],
+    ) {
+        self.workload_budgets = get_workload_budgets(self, max_hours_per_day, reserved_free_time);
+    }
+
+    /// Hours spent before the calendar count toward the first week of the budgets of their goal.
+    /// They can't take a budget over its max - the rest of the week then just gets nothing.
+    /// A partial first week with hours spent isn't prorated: the spent hours are from its days
+    /// before the calendar, so the min of the full week applies - as far as its days can still get to it.
+    pub fn add_hours_spent(&mut self, hours_spent: &[HoursSpent]) {
+        for spent in hours_spent {
+            for budget in &mut self.budgets {
+                if !budget.participating_goals.contains(&spent.goalid) {
+                    continue;
+                }
+                let max_of_days_in_first_week = budget
+                    .time_budgets
+                    .iter()
+                    .find(|time_budget| {
+                        time_budget.time_budget_type == Week
+                            && time_budget.calendar_start_index == 24
+                    })
+                    .map_or(0, |week| {
+                        get_max_of_days_in(
+                            &budget.time_budgets,
+                            week.calendar_start_index,
+                            week.calendar_end_index,
+                        )
+                    });
+                for time_budget in &mut budget.time_budgets {
+                    if time_budget.time_budget_type == Week
+                        && time_budget.calendar_start_index == 24
+                    {
+                        let counted = min(
+                            spent.hours,
+                            time_budget.max_scheduled - time_budget.scheduled,
+                        );
+                        time_budget.scheduled += counted;
+                        time_budget.spent += counted;
+                        if let Some(min_of_full_week) = time_budget.min_of_full_week {
+                            if time_budget.spent > 0 {
+                                time_budget.min_scheduled = min(
+                                    min_of_full_week,
+                                    time_budget.spent + max_of_days_in_first_week,
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hours before now are in the past: goals can't use them anymore, only tasks completed today are placed there.
+    /// Day budgets then only expect the hours of their filter that are left after now.
+    pub fn make_hours_before_unavailable(
+        &mut self,
+        now: NaiveDateTime,
+        goal_map: &mut BTreeMap<String, Goal>,
+    ) {
+        //an hour that has already started is in the past as well
+        let mut now_hour = now.with_minute(0).unwrap().with_second(0).unwrap();
+        if now_hour < now {
+            now_hour = now_hour.add(Duration::hours(1));
+        }
+        let now_hour = min(max(now_hour, self.start_date_time), self.end_date_time);
+        if now_hour == self.start_date_time {
+            return;
+        }
+
+        for goal in goal_map.values_mut() {
+            goal.not_on.get_or_insert_with(Vec::new).push(Slot {
+                start: self.start_date_time.sub(Duration::days(1)),
+                end: now_hour,
+            });
+        }
+
+        let now_index = self.get_index_of(now_hour);
+        self.now_index = Some(now_index);
+        for budget in &mut self.budgets {
+            for time_budget in &mut budget.time_budgets {
+                if time_budget.time_budget_type != Day
+                    || time_budget.calendar_start_index >= now_index
+                {
+                    continue;
+                }
+                let hours_left = (max(now_index, time_budget.calendar_start_index)
+                    ..time_budget.calendar_end_index)
+                    .filter(|index| {
+                        let date_time = self
+                            .start_date_time
+                            .add(Duration::hours(*index as i64 - 24));
+                        budget.time_filters.on_days.contains(&date_time.weekday())
+                            && budget
+                                .time_filters
+                                .contains_hour(date_time.weekday(), date_time.hour() as usize)
+                    })
+                    .count();
+                time_budget.min_scheduled = min(time_budget.min_scheduled, hours_left);
+            }
+        }
+    }
+
+    pub fn log_impossible_activities(
+        &mut self,
+        activities: &Vec<Activity>,
+        goal_map: &BTreeMap<String, Goal>,
+    ) {
         for budget in &self.budgets {
             for time_budget in &budget.time_budgets {
                 if time_budget.time_budget_type != Day {
@@ -547,9 +1293,17 @@ impl Calendar {
                     continue;
                 }
                 if time_budget.scheduled < time_budget.min_scheduled
-                    && time_budget.calendar_end_index < self.get_index_of(self.end_date_time)
+                    && !self.is_over_edge_of_calendar(time_budget)
                 // exempt budgets that run over edge of calendar
                 {
+                    let usable_intervals = interval_helper::get_intervals_in_filter(
+                        self,
+                        Some(&budget.time_filters),
+                        self.get_datetime_of(time_budget.calendar_start_index),
+                        self.get_datetime_of(time_budget.calendar_end_index),
+                        &None,
+                    );
+                    let limited_by = self.get_workload_limits_reached(&usable_intervals, 1);
                     self.impossible_activities.push(ImpossibleActivity {
                         id: budget.originating_goal_id.clone(),
                         hours_missing: time_budget.min_scheduled - time_budget.scheduled,
@@ -560,6 +1314,7 @@ impl Calendar {
                             self.start_date_time
                                 .add(Duration::hours(time_budget.calendar_end_index as i64)),
                         ),
+                        limited_by,
                     });
                 }
             }
@@ -569,25 +1324,93 @@ impl Calendar {
                 && activity.deadline.is_some()
                 && activity.activity_type != TopUpWeekBudget
                 && activity.deadline.unwrap() <= self.end_date_time
+                && !self.is_for_day_over_edge_of_calendar(activity)
             // exempt activities that run over edge of calendar
             {
+                let limited_by = match goal_map.get(&activity.goal_id) {
+                    Some(goal) => {
+                        let budget_filters = self.get_filters_for(&goal.id);
+                        let usable_intervals = interval_helper::get_intervals_in_filter(
+                            self,
+                            budget_filters.as_ref().or(goal.filters.as_ref()),
+                            activity.start,
+                            min(
+                                activity.deadline.unwrap_or(self.end_date_time),
+                                self.end_date_time,
+                            ),
+                            &goal.not_on,
+                        );
+                        self.get_workload_limits_reached(&usable_intervals, activity.min_block_size)
+                    }
+                    None => vec![],
+                };
                 self.impossible_activities.push(ImpossibleActivity {
                     id: activity.goal_id.clone(),
                     hours_missing: activity.duration_left,
                     period_start_date_time: activity.start,
                     period_end_date_time: activity.deadline,
+                    limited_by,
                 });
             }
         }
     }
 
-    pub(crate) fn get_filters_for(&self, id: &str) -> Option<&super::goal::Filter> {
-        for budget in &self.budgets {
-            if budget.participating_goals.iter().any(|s| s == id) {
-                return Some(&budget.time_filters);
+    fn is_over_edge_of_calendar(&self, time_budget: &TimeBudget) -> bool {
+        time_budget.calendar_start_index < self.get_index_of(self.start_date_time)
+            || time_budget.calendar_end_index >= self.get_index_of(self.end_date_time)
+    }
+
+    /// Min day activities only get the part of a day inside the calendar,
+    /// so the day they are for is looked up in the budget of the goal
+    fn is_for_day_over_edge_of_calendar(&self, activity: &Activity) -> bool {
+        activity.activity_type == GetToMinDayBudget
+            && self
+                .budgets
+                .iter()
+                .filter(|budget| budget.originating_goal_id == activity.goal_id)
+                .flat_map(|budget| budget.time_budgets.iter())
+                .any(|time_budget| {
+                    time_budget.time_budget_type == Day
+                        && self.get_datetime_of(time_budget.calendar_start_index) <= activity.start
+                        && activity.start < self.get_datetime_of(time_budget.calendar_end_index)
+                        && self.is_over_edge_of_calendar(time_budget)
+                })
+    }
+
+    /// Workload limits without room for a block of the activity in some of the intervals it could use
+    fn get_workload_limits_reached(
+        &self,
+        usable_intervals: &[Interval],
+        min_block_size: usize,
+    ) -> Vec<WorkloadLimit> {
+        let mut limits: Vec<WorkloadLimit> = vec![];
+        for workload_budget in &self.workload_budgets {
+            let time_budget = &workload_budget.time_budget;
+            let overlaps_usable_interval = usable_intervals.iter().any(|interval| {
+                time_budget.calendar_start_index < interval.end
+                    && time_budget.calendar_end_index > interval.start
+            });
+            if overlaps_usable_interval
+                && time_budget
+                    .max_scheduled
+                    .saturating_sub(time_budget.scheduled)
+                    < min_block_size
+                && !limits.contains(&workload_budget.limit)
+            {
+                limits.push(workload_budget.limit);
             }
         }
-        None
+        limits
+    }
+
+    /// The filters of the budgets a goal participates in.
+    /// A goal in more than one budget can only use the hours inside the filters of all of them.
+    pub(crate) fn get_filters_for(&self, id: &str) -> Option<Filter> {
+        self.budgets
+            .iter()
+            .filter(|budget| budget.participating_goals.iter().any(|s| s == id))
+            .map(|budget| budget.time_filters.clone())
+            .reduce(|filter, other| filter.intersect(&other))
     }
 }
 
@@ -600,23 +1423,82 @@ impl Debug for Calendar {
             self.impossible_activities.len()
         )?;
         for budget in &self.budgets {
-            writeln!(f, "{:?}", &budget)?;
+            writeln!(f, "{:?}", budget)?;
+        }
+        for tag_budget in &self.tag_budgets {
+            writeln!(f, "{:?}", tag_budget)?;
+        }
+        for goal_cap in &self.goal_caps {
+            writeln!(f, "{:?}", goal_cap)?;
+        }
+        for workload_budget in &self.workload_budgets {
+            writeln!(f, "{:?}", workload_budget)?;
         }
         for interval in &self.intervals {
-            writeln!(f, "{:?}", &interval)?;
+            writeln!(f, "{:?}", interval)?;
         }
         Ok(())
     }
 }
 
-fn consolidate_intervals_on_goal_id(cal_ints: &mut Vec<CalendarInterval>) {
+/// Hours of the same goal become one task, unless one of them was already completed today,
+/// or placed again for a missed task.
+/// The other task metadata is taken from the first hour of the task.
+/// Free hours become one task if the same goals can use them, and the same goals are blocked from them.
+fn is_same_task(
+    cal_int: &CalendarInterval,
+    other: &CalendarInterval,
+    activities: &[Activity],
+    free_time: &BTreeMap<usize, FreeTime>,
+) -> bool {
+    if cal_int.status != other.status {
+        return false;
+    }
+    match (&cal_int.status, &other.status) {
+        (CalIntStatus::Occupied(act_index, _), CalIntStatus::Occupied(other_act_index, _)) => {
+            activities[*act_index].completed_today == activities[*other_act_index].completed_today
+                && activities[*act_index].carried_over == activities[*other_act_index].carried_over
+        }
+        _ => free_time.get(&cal_int.interval.start) == free_time.get(&other.interval.start),
+    }
+}
+
+/// Gives every free hour its own interval, so free time can be split where claimability changes
+fn split_free_intervals_per_hour(intervals: &mut Vec<CalendarInterval>) {
+    *intervals = intervals
+        .drain(..)
+        .flat_map(|cal_int| match cal_int.status {
+            Claimable(_) => (cal_int.interval.start..cal_int.interval.end)
+                .map(|hour_index| CalendarInterval {
+                    interval: Interval {
+                        start: hour_index,
+                        end: hour_index + 1,
+                    },
+                    status: cal_int.status.clone(),
+                })
+                .collect(),
+            CalIntStatus::Occupied(..) => vec![cal_int],
+        })
+        .collect();
+}
+
+fn consolidate_intervals_on_goal_id(
+    cal_ints: &mut Vec<CalendarInterval>,
+    activities: &[Activity],
+    free_time: &BTreeMap<usize, FreeTime>,
+) {
     if cal_ints.is_empty() {
         return;
     }
 
     let mut write_index = 0;
     for read_index in 1..cal_ints.len() {
-        if cal_ints[read_index].status == cal_ints[write_index].status {
+        if is_same_task(
+            &cal_ints[read_index],
+            &cal_ints[write_index],
+            activities,
+            free_time,
+        ) {
             // Extend the current interval
             cal_ints[write_index].interval.end = cal_ints[read_index].interval.end;
         } else {
@@ -683,9 +1565,34 @@ fn split_intervals_on_day_boundaries(intervals: &mut Vec<CalendarInterval>) {
     }
 }
 
+/// The budget nested deepest of those a goal counts toward.
+/// Of budgets that aren't nested in each other, the one with the lowest id.
+fn get_innermost_budget_id(budgets: &[Budget], goal_id: &String) -> Option<String> {
+    let goal_budgets: Vec<&Budget> = budgets
+        .iter()
+        .filter(|budget| budget.participating_goals.contains(goal_id))
+        .collect();
+    let has_inner_budget = |budget: &Budget| {
+        goal_budgets.iter().any(|inner| {
+            inner.originating_goal_id != budget.originating_goal_id
+                && budget
+                    .participating_goals
+                    .contains(&inner.originating_goal_id)
+        })
+    };
+    goal_budgets
+        .iter()
+        .filter(|budget| !has_inner_budget(budget))
+        .map(|budget| budget.originating_goal_id.clone())
+        .min()
+}
+
 fn transform_intervals_to_day_tasks(
     intervals: Vec<CalendarInterval>,
     activities: &Vec<Activity>,
+    budgets: &[Budget],
+    parent_goal_ids: &BTreeMap<String, String>,
+    free_time: &BTreeMap<usize, FreeTime>,
     calendar_start: NaiveDateTime,
 ) -> Vec<DayTasks> {
     let mut task_counter: usize = 0;
@@ -719,21 +1626,67 @@ fn transform_intervals_to_day_tasks(
         } else {
             (24 - start) + end
         };
+        let activity = match interval.status {
+            CalIntStatus::Occupied(act_index, ..) => Some(&activities[act_index]),
+            Claimable(_) => None,
+        };
+        let free = free_time.get(&interval.interval.start);
+        //free time no goal can use anymore, because some goals have it in their not on
+        let is_blocked =
+            free.is_some_and(|free| free.claimable_by.is_empty() && !free.blocked_for.is_empty());
+        let is_past = free.is_some_and(|free| free.is_past);
+        let task_start = calendar_start.add(Duration::hours(day_start as i64 + start as i64 - 24));
         let task = Task {
             taskid: task_counter,
             goalid: match interval.status {
                 CalIntStatus::Occupied(.., ref goal_id) => goal_id.clone(),
+                Claimable(_) if is_past => "past".to_string(),
+                Claimable(_) if is_blocked => "blocked".to_string(),
                 Claimable(_) => "free".to_string(),
             },
             title: match interval.status {
                 CalIntStatus::Occupied(act_index, ..) => activities[act_index].title.clone(),
+                Claimable(_) if is_past => "past".to_string(),
+                Claimable(_) if is_blocked => "blocked".to_string(),
                 Claimable(_) => "free".to_string(),
             },
             duration,
-            start: calendar_start.add(Duration::hours(day_start as i64 + start as i64 - 24)),
+            start: task_start,
             deadline: calendar_start.add(Duration::hours(
                 day_start as i64 + start as i64 + duration as i64 - 24,
             )),
+            activity_type: activity.map(|activity| activity.activity_type.clone()),
+            best_effort: activity.is_some_and(|activity| activity.is_best_effort),
+            budget_id: activity
+                .and_then(|activity| get_innermost_budget_id(budgets, &activity.goal_id)),
+            parent_goal_id: activity
+                .and_then(|activity| parent_goal_ids.get(&activity.goal_id).cloned()),
+            completed_today: activity.is_some_and(|activity| activity.completed_today),
+            claimable_by: free
+                .map(|free| free.claimable_by.iter().cloned().collect())
+                .unwrap_or_default(),
+            blocked_for: free
+                .map(|free| free.blocked_for.iter().cloned().collect())
+                .unwrap_or_default(),
+            in_preferred_window: activity
+                .filter(|activity| !activity.preferred_windows.is_empty())
+                .map(|activity| {
+                    (0..duration).all(|hour| {
+                        let hour_of_day = task_start.add(Duration::hours(hour as i64)).hour();
+                        activity
+                            .preferred_windows
+                            .iter()
+                            .any(|window| window.contains_hour(hour_of_day as usize))
+                    })
+                }),
+            hours_late: activity
+                .and_then(|activity| activity.deadline)
+                .map(|deadline| {
+                    (task_start.add(Duration::hours(duration as i64)) - deadline).num_hours()
+                })
+                .filter(|hours_late| *hours_late > 0)
+                .map(|hours_late| hours_late as usize),
+            carried_over: activity.is_some_and(|activity| activity.carried_over),
         };
 
         if day_start > 0 {
@@ -756,6 +1709,9 @@ fn transform_intervals_to_day_tasks(
             day_tasks.extend(transform_intervals_to_day_tasks(
                 vec![remaining_interval],
                 activities,
+                budgets,
+                parent_goal_ids,
+                free_time,
                 calendar_start,
             ));
         }
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt;
 use std::fmt::Debug;
 
@@ -16,6 +16,108 @@ impl CalendarInterval {
     }
 }
 
+/// The calendar intervals, ordered by start.
+/// They never overlap and together cover the whole calendar, so indexing them on start is enough
+/// to find all intervals overlapping a given range in logarithmic time - no interval tree needed.
+#[derive(Clone)]
+pub struct CalendarIntervals {
+    by_start: BTreeMap<usize, CalendarInterval>,
+}
+
+impl CalendarIntervals {
+    pub fn new(cal_ints: Vec<CalendarInterval>) -> Self {
+        Self {
+            by_start: cal_ints
+                .into_iter()
+                .map(|cal_int| (cal_int.interval.start, cal_int))
+                .collect(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &CalendarInterval> {
+        self.by_start.values()
+    }
+
+    pub fn to_vec(&self) -> Vec<CalendarInterval> {
+        self.by_start.values().cloned().collect()
+    }
+
+    /// End of the last interval, which is the number of hours in the calendar
+    pub fn end(&self) -> usize {
+        self.by_start
+            .last_key_value()
+            .expect("when calling end there should be at least one interval in calendar.")
+            .1
+            .interval
+            .end
+    }
+
+    /// Start of the interval containing index - which is where overlaps with a range starting at index begin
+    fn first_key_for(&self, index: usize) -> usize {
+        self.by_start
+            .range(..=index)
+            .next_back()
+            .map_or(index, |(start, _)| *start)
+    }
+
+    pub(crate) fn overlapping(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> impl Iterator<Item = &CalendarInterval> {
+        let first_key = self.first_key_for(start);
+        self.by_start
+            .range(first_key..end.max(first_key))
+            .map(|(_, cal_int)| cal_int)
+            .filter(move |cal_int| cal_int.interval.end > start)
+    }
+
+    pub(crate) fn overlapping_mut(
+        &mut self,
+        start: usize,
+        end: usize,
+    ) -> impl Iterator<Item = &mut CalendarInterval> {
+        let first_key = self.first_key_for(start);
+        self.by_start
+            .range_mut(first_key..end.max(first_key))
+            .map(|(_, cal_int)| cal_int)
+            .filter(move |cal_int| cal_int.interval.end > start)
+    }
+
+    /// Make sure an interval starts at index, splitting the interval containing it if needed.
+    /// Both parts keep the status (and claims) of the original interval.
+    pub(crate) fn split_at(&mut self, index: usize) {
+        let first_key = self.first_key_for(index);
+        if first_key == index {
+            return;
+        }
+        if let Some(cal_int) = self.by_start.get_mut(&first_key) {
+            if cal_int.interval.end <= index {
+                return;
+            }
+            let mut end_part = cal_int.clone();
+            cal_int.interval.end = index;
+            end_part.interval.start = index;
+            self.by_start.insert(index, end_part);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a CalendarIntervals {
+    type Item = &'a CalendarInterval;
+    type IntoIter = std::collections::btree_map::Values<'a, usize, CalendarInterval>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.by_start.values()
+    }
+}
+
+impl Debug for CalendarIntervals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.by_start.values()).finish()
+    }
+}
+
 #[derive(Clone)]
 pub enum CalIntStatus {
     Claimable(HashSet<usize>),
@@ -38,7 +140,7 @@ impl Debug for CalIntStatus {
             CalIntStatus::Claimable(claims) => {
                 write!(f, "Number of claims: {}", claims.len()).expect("expecting result");
                 for act_index in claims {
-                    write!(f, "\nby {}", &act_index).expect("expecting result");
+                    write!(f, "\nby {}", act_index).expect("expecting result");
                 }
                 Ok(())
             }
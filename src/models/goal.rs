@@ -1,9 +1,11 @@
+use std::collections::HashMap;
 use std::ops::{Add, Sub};
 
-use chrono::{Datelike, Duration, NaiveDateTime, Weekday};
-use serde::Deserialize;
+use chrono::{Datelike, Duration, NaiveDateTime, Timelike, Weekday};
+use serde::{Deserialize, Serialize};
 
 use super::calendar::Calendar;
+use crate::services::interval_helper;
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -13,6 +15,8 @@ pub struct Goal {
     pub start: NaiveDateTime,
     #[serde(default)]
     pub deadline: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub deadline_mode: DeadlineMode,
     #[serde(rename = "budget")]
     pub budget_config: Option<BudgetConfig>,
     pub filters: Option<Filter>,
@@ -20,6 +24,68 @@ pub struct Goal {
     pub title: String,
     pub children: Option<Vec<String>>,
     pub not_on: Option<Vec<Slot>>,
+    /// Soft windows on every day: hours in them are preferred, but other compatible hours can still be used
+    #[serde(default)]
+    pub preferred_windows: Vec<TimeWindow>,
+    /// Categories across the goal tree, for example 'screen' - capped together by the tag caps in the input
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// How the hours of a goal without a budget are spread over the days up to its deadline
+    #[serde(default)]
+    pub distribution: Option<Distribution>,
+    /// Max hours per day for a goal without a budget
+    #[serde(default)]
+    pub max_per_day: Option<usize>,
+    /// Between positions with as little conflict, take the earliest or the latest
+    #[serde(default)]
+    pub placement: Placement,
+    /// Days this goal has to share with another goal, or stay away from - the other goal can be itself
+    #[serde(default)]
+    pub day_constraints: Vec<DayConstraint>,
+}
+
+/// A relation between the days of this goal and the days of another goal
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct DayConstraint {
+    pub goalid: String,
+    pub relation: DayRelation,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum DayRelation {
+    SameDay,
+    NotSameDay,
+    NotConsecutiveDays,
+}
+
+/// As soon as possible or as late as possible
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum Placement {
+    #[default]
+    Asap,
+    Alap,
+}
+
+/// Spread the hours evenly over the days, or fill the first or last days first
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Distribution {
+    Even,
+    FrontLoad,
+    BackLoad,
+}
+
+/// A hard deadline is met or the goal is impossible.
+/// A goal with a soft deadline that can't fit before it gets placed late, after the deadline.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum DeadlineMode {
+    #[default]
+    Hard,
+    Soft,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -34,6 +100,158 @@ pub struct Filter {
     pub after_time: usize,
     pub before_time: usize,
     pub on_days: Vec<Weekday>,
+    /// More windows on the same days, next to after_time-before_time
+    #[serde(default)]
+    pub extra_windows: Vec<TimeWindow>,
+    /// Windows for specific days of the week, replacing the windows above on those days
+    /// The day still needs to be in on_days
+    #[serde(default)]
+    pub day_windows: HashMap<Weekday, Vec<TimeWindow>>,
+}
+
+/// Hours of the day between after_time and before_time - crossing midnight if after_time is later
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeWindow {
+    pub after_time: usize,
+    pub before_time: usize,
+}
+
+impl TimeWindow {
+    pub fn crosses_midnight(&self) -> bool {
+        self.after_time > self.before_time
+    }
+
+    pub fn contains_hour(&self, hour_of_day: usize) -> bool {
+        if self.after_time < self.before_time {
+            hour_of_day >= self.after_time && hour_of_day < self.before_time
+        } else {
+            hour_of_day >= self.after_time || hour_of_day < self.before_time
+        }
+    }
+}
+
+impl Filter {
+    pub fn windows(&self) -> Vec<TimeWindow> {
+        let mut windows = vec![TimeWindow {
+            after_time: self.after_time,
+            before_time: self.before_time,
+        }];
+        windows.extend(self.extra_windows.iter().copied());
+        windows
+    }
+
+    pub fn windows_on(&self, weekday: Weekday) -> Vec<TimeWindow> {
+        match self.day_windows.get(&weekday) {
+            Some(windows) => windows.clone(),
+            None => self.windows(),
+        }
+    }
+
+    /// The first window crossing midnight - looking at the windows for all days
+    /// before the windows for specific days, from Monday to Sunday.
+    /// Activities of the filter can start at its after_time the day before their start.
+    pub fn get_window_crossing_midnight(&self) -> Option<TimeWindow> {
+        let mut day_windows: Vec<(&Weekday, &Vec<TimeWindow>)> = self.day_windows.iter().collect();
+        day_windows.sort_by_key(|(weekday, _)| weekday.num_days_from_monday());
+        self.windows()
+            .into_iter()
+            .chain(
+                day_windows
+                    .into_iter()
+                    .flat_map(|(_, windows)| windows.iter().copied()),
+            )
+            .find(|window| window.crosses_midnight())
+    }
+
+    /// The after_time of a window crossing midnight on a day - if the filter is on that day
+    fn get_after_time_crossing_midnight(&self, weekday: Weekday) -> Option<usize> {
+        if !self.on_days.contains(&weekday) {
+            return None;
+        }
+        self.windows_on(weekday)
+            .into_iter()
+            .find(|window| window.crosses_midnight())
+            .map(|window| window.after_time)
+    }
+
+    /// Hour of the day at which a day of a budget starts.
+    /// A window crossing midnight that goes on from the day before, like a night of sleep,
+    /// makes the day start at its after_time - any other day starts at midnight.
+    pub fn get_day_start_hour(&self, weekday: Weekday) -> usize {
+        match (
+            self.get_after_time_crossing_midnight(weekday.pred()),
+            self.get_after_time_crossing_midnight(weekday),
+        ) {
+            (Some(_), Some(after_time)) => after_time,
+            _ => 0,
+        }
+    }
+
+    /// Number of hours from start to end inside the windows of the days they are on
+    pub fn hours_between(&self, start: NaiveDateTime, end: NaiveDateTime) -> usize {
+        let mut hours = 0;
+        let mut hour = start;
+        while hour < end {
+            if self.on_days.contains(&hour.weekday())
+                && self.contains_hour(hour.weekday(), hour.hour() as usize)
+            {
+                hours += 1;
+            }
+            hour = hour.add(Duration::hours(1));
+        }
+        hours
+    }
+
+    pub fn contains_hour(&self, weekday: Weekday, hour_of_day: usize) -> bool {
+        self.windows_on(weekday)
+            .iter()
+            .any(|window| window.contains_hour(hour_of_day))
+    }
+
+    /// Number of hours of the day inside the windows for that day
+    pub fn hours_on(&self, weekday: Weekday) -> usize {
+        (0..24)
+            .filter(|hour_of_day| self.contains_hour(weekday, *hour_of_day))
+            .count()
+    }
+
+    /// The hours inside both filters, with the windows given per day of the week.
+    /// Days without any hour in common are left out of on_days.
+    pub fn intersect(&self, other: &Filter) -> Filter {
+        let mut on_days: Vec<Weekday> = vec![];
+        let mut day_windows: HashMap<Weekday, Vec<TimeWindow>> = HashMap::new();
+        for weekday in self
+            .on_days
+            .iter()
+            .filter(|day| other.on_days.contains(day))
+        {
+            let hours_in_both: Vec<bool> = (0..24)
+                .map(|hour_of_day| {
+                    self.contains_hour(*weekday, hour_of_day)
+                        && other.contains_hour(*weekday, hour_of_day)
+                })
+                .collect();
+            let windows: Vec<TimeWindow> = interval_helper::reduce(&hours_in_both)
+                .into_iter()
+                .map(|interval| TimeWindow {
+                    after_time: interval.start,
+                    before_time: interval.end,
+                })
+                .collect();
+            if !windows.is_empty() {
+                on_days.push(*weekday);
+                day_windows.insert(*weekday, windows);
+            }
+        }
+        Filter {
+            after_time: 0,
+            before_time: 24,
+            on_days,
+            extra_windows: vec![],
+            day_windows,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -43,6 +261,36 @@ pub struct BudgetConfig {
     pub max_per_day: usize,
     pub min_per_week: usize,
     pub max_per_week: usize,
+    /// Min and max for specific days of the week, replacing min_per_day and max_per_day on those days
+    #[serde(default)]
+    pub day_budgets: HashMap<Weekday, DayBudget>,
+    /// Once placed on a day, prefer the same hour of the day on the other days
+    #[serde(default)]
+    pub consistent_time: bool,
+}
+
+/// Override of the day budget for one day of the week - leaving out min or max keeps the one for all days
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct DayBudget {
+    pub min: Option<usize>,
+    pub max: Option<usize>,
+}
+
+impl BudgetConfig {
+    pub fn min_on(&self, weekday: Weekday) -> usize {
+        self.day_budgets
+            .get(&weekday)
+            .and_then(|day_budget| day_budget.min)
+            .unwrap_or(self.min_per_day)
+    }
+
+    pub fn max_on(&self, weekday: Weekday) -> usize {
+        self.day_budgets
+            .get(&weekday)
+            .and_then(|day_budget| day_budget.max)
+            .unwrap_or(self.max_per_day)
+    }
 }
 
 impl Goal {
@@ -64,18 +312,16 @@ impl Goal {
         }
 
         let filter_option = self.filters.clone().unwrap();
-        if filter_option.after_time < filter_option.clone().before_time {
-            //normal case
-        } else {
+        if let Some(window) = filter_option.get_window_crossing_midnight() {
             // special case where we know that compatible times cross the midnight boundary
             println!(
                 "Special case adjusting activity start from {:?}",
-                &adjusted_goal_start
+                adjusted_goal_start
             );
             adjusted_goal_start = adjusted_goal_start
                 .sub(Duration::hours(24))
-                .add(Duration::hours(filter_option.after_time as i64));
-            println!("... to {:?}", &adjusted_goal_start);
+                .add(Duration::hours(window.after_time as i64));
+            println!("... to {:?}", adjusted_goal_start);
             adjusted_goal_deadline = Some(
                 adjusted_goal_start.add(Duration::days(
                     (adjusted_goal_deadline.unwrap_or(calendar.end_date_time)
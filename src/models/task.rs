@@ -2,15 +2,26 @@
 use chrono::{NaiveDate, NaiveDateTime};
 use serde::{Deserialize, Serialize};
 
-use super::calendar::ImpossibleActivity;
+use super::activity::ActivityType;
+use super::budget::TimeBudgetType;
+use super::calendar::{DeferredHours, ImpossibleActivity};
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct FinalTasks {
     pub scheduled: Vec<DayTasks>,
     pub impossible: Vec<ImpossibleActivity>,
+    /// Hours left for a later schedule, because a distribution gave them to days after the calendar
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deferred: Vec<DeferredHours>,
+    #[serde(default)]
+    pub budgets: Vec<BudgetProgress>,
+    /// Goals in more than one budget that have no hour in common to be scheduled in
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub budget_conflicts: Vec<BudgetConflict>,
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Task {
     pub taskid: usize,
@@ -19,14 +30,99 @@ pub struct Task {
     pub duration: usize,
     pub start: NaiveDateTime,
     pub deadline: NaiveDateTime,
+    #[serde(default)]
+    pub activity_type: Option<ActivityType>,
+    #[serde(default)]
+    pub best_effort: bool,
+    /// The budget this task counts toward - the first one, for a goal in more than one budget
+    #[serde(default)]
+    pub budget_id: Option<String>,
+    #[serde(default)]
+    pub parent_goal_id: Option<String>,
+    #[serde(default)]
+    pub completed_today: bool,
+    /// For free time: the goals that could still use it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub claimable_by: Vec<String>,
+    /// For free time: the goals that have it in their not on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blocked_for: Vec<String>,
+    /// For goals with preferred windows: if all hours of the task are inside them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_preferred_window: Option<bool>,
+    /// For goals with a soft deadline: how many hours after the deadline the task ends
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hours_late: Option<usize>,
+    /// Placed again for a missed task
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub carried_over: bool,
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DayTasks {
     pub day: NaiveDate,
     pub tasks: Vec<Task>,
 }
 
+/// How far each day and week of a budget got towards its min and max
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BudgetProgress {
+    pub id: String,
+    /// Hour of the day the first hours went to, for a budget with consistent time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor_hour: Option<usize>,
+    pub windows: Vec<BudgetWindow>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BudgetWindow {
+    pub window_type: TimeBudgetType,
+    pub period_start_date_time: NaiveDateTime,
+    pub period_end_date_time: NaiveDateTime,
+    pub scheduled: usize,
+    pub min: usize,
+    pub max: usize,
+    /// Hours spent before the calendar that count toward this window
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub spent: usize,
+    /// A week cut off by the start or end of the calendar - with its min prorated to its days
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub partial: bool,
+}
+
+/// A goal in more than one budget, without any hour inside the filters of all of those budgets
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BudgetConflict {
+    pub goalid: String,
+    pub budget_ids: Vec<String>,
+}
+
+fn is_zero(hours: &usize) -> bool {
+    *hours == 0
+}
+
+fn is_false(flag: &bool) -> bool {
+    !*flag
+}
+
+/// Hours already spent on a goal before the calendar starts, for example last week
+#[derive(Deserialize, Debug, Clone)]
+pub struct HoursSpent {
+    pub goalid: String,
+    pub hours: usize,
+}
+
+/// A task from an earlier schedule that didn't get done - its hours get placed again
+#[derive(Deserialize, Debug, Clone)]
+pub struct MissedTask {
+    pub goalid: String,
+    pub start: NaiveDateTime,
+    pub deadline: NaiveDateTime,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct TaskCompletedToday {
     pub goalid: String,
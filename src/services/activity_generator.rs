@@ -1,7 +1,9 @@
 use crate::models::activity::{ActivityStatus, ActivityType};
+use crate::models::goal::DeadlineMode;
 use crate::models::interval::Interval;
-use crate::models::task::TaskCompletedToday;
+use crate::models::task::{HoursSpent, MissedTask, TaskCompletedToday};
 use crate::models::{activity::Activity, budget::TimeBudgetType, calendar::Calendar, goal::Goal};
+use std::cmp::max;
 use std::collections::BTreeMap;
 
 pub fn add_budget_min_week_activities(
@@ -42,6 +44,7 @@ pub fn add_budget_min_week_activities(
                             goal_to_use,
                             calendar,
                             time_budget,
+                            calendar.get_min_hours_of_inner_budgets(budget, time_budget),
                         ),
                     );
                 }
@@ -64,19 +67,23 @@ pub fn add_budget_top_up_week_activities(
             .values()
             .find(|g| g.id.eq(&budget.originating_goal_id))
         {
-            let mut max_per_week = 0;
-            for time_budget in &budget.time_budgets {
-                if time_budget.time_budget_type == TimeBudgetType::Week
-                    && time_budget.scheduled != time_budget.max_scheduled
-                {
-                    max_per_week = time_budget.max_scheduled;
-                }
-            }
             for time_budget in &budget.time_budgets {
                 if time_budget.time_budget_type == TimeBudgetType::Day
                     && time_budget.min_scheduled < time_budget.max_scheduled
                     && time_budget.scheduled < time_budget.max_scheduled
                 {
+                    //a day crossing midnight into the calendar goes with the first week
+                    let day_start = max(time_budget.calendar_start_index, 24);
+                    let max_per_week = budget
+                        .time_budgets
+                        .iter()
+                        .find(|week_budget| {
+                            week_budget.time_budget_type == TimeBudgetType::Week
+                                && week_budget.calendar_start_index <= day_start
+                                && day_start < week_budget.calendar_end_index
+                        })
+                        .filter(|week_budget| week_budget.scheduled != week_budget.max_scheduled)
+                        .map_or(0, |week_budget| week_budget.max_scheduled);
                     top_up_activities.extend(Activity::get_activities_to_top_up_week_budget(
                         goal_to_use,
                         calendar,
@@ -91,9 +98,49 @@ pub fn add_budget_top_up_week_activities(
     activities.extend(top_up_activities);
 }
 
+/// Activities of goals with a soft deadline that couldn't fit before it get another chance after it
+pub(crate) fn allow_soft_deadline_activities_to_be_late(
+    calendar: &mut Calendar,
+    goals: &BTreeMap<String, Goal>,
+    activities: &mut [Activity],
+) {
+    println!("Allowing impossible activities with a soft deadline to be late...");
+    for (act_index, activity) in activities.iter_mut().enumerate() {
+        if activity.status != ActivityStatus::Impossible
+            || activity.activity_type != ActivityType::SimpleGoal
+            || activity.completed_today
+        {
+            continue;
+        }
+        let Some(goal) = goals.get(&activity.goal_id) else {
+            continue;
+        };
+        let Some(deadline) = goal.deadline else {
+            continue;
+        };
+        if goal.deadline_mode != DeadlineMode::Soft || deadline >= calendar.end_date_time {
+            continue;
+        }
+
+        //claims from before the deadline are of no use anymore
+        for interval in &activity.compatible_intervals {
+            calendar.unregister(interval, act_index);
+        }
+        activity.compatible_intervals =
+            activity.get_compatible_intervals_after_deadline(goal, calendar, deadline);
+        for interval in &activity.compatible_intervals {
+            calendar.register(interval, act_index);
+        }
+        activity.incompatible_intervals = vec![];
+        activity.flex_reset();
+        activity.status = ActivityStatus::Unprocessed;
+    }
+}
+
 pub(crate) fn add_simple_activities(
     calendar: &mut Calendar,
     goals: &BTreeMap<String, Goal>,
+    hours_spent: &[HoursSpent],
     activities: &mut Vec<Activity>,
 ) {
     println!("Adding simple activities...");
@@ -121,15 +168,41 @@ pub(crate) fn add_simple_activities(
                 }
             }
         }
+        let hours_spent_on_goal: usize = hours_spent
+            .iter()
+            .filter(|spent| spent.goalid == goal.id)
+            .map(|spent| spent.hours)
+            .sum();
         simple_activities.extend(Activity::get_simple_activities(
             goal,
             calendar,
             duration_of_children,
+            hours_spent_on_goal,
         ));
     }
     activities.extend(simple_activities);
 }
 
+/// The hours of missed tasks are added to their goal again, as separate activities
+pub(crate) fn add_missed_activities(
+    calendar: &Calendar,
+    goals: &BTreeMap<String, Goal>,
+    missed_tasks: &[MissedTask],
+    activities: &mut Vec<Activity>,
+) {
+    println!("Adding activities for missed tasks...");
+    for missed_task in missed_tasks {
+        if let Some(goal) = goals.get(&missed_task.goalid) {
+            let hours_missed = (missed_task.deadline - missed_task.start).num_hours();
+            activities.extend(Activity::get_missed_activities(
+                goal,
+                calendar,
+                hours_missed.max(0) as usize,
+            ));
+        }
+    }
+}
+
 pub(crate) fn add_budget_min_day_activities(
     calendar: &mut Calendar,
     goals: &BTreeMap<String, Goal>,
@@ -154,6 +227,7 @@ pub(crate) fn add_budget_min_day_activities(
                         goal_to_use,
                         calendar,
                         time_budget,
+                        calendar.get_min_hours_of_inner_budgets(budget, time_budget),
                     ));
                 }
             }
@@ -195,6 +269,12 @@ pub(crate) fn add_tasks_completed_today(
                 }],
                 incompatible_intervals: vec![],
                 flex: Some(1),
+                completed_today: true,
+                is_best_effort: false,
+                preferred_windows: matching_goal.preferred_windows.clone(),
+                placement: matching_goal.placement,
+                carried_over: false,
+                has_soft_deadline: false,
             });
         }
     }
@@ -6,23 +6,30 @@ use crate::models::activity::ActivityType::{
 use crate::models::budget::TimeBudgetType::Week;
 use crate::models::calendar_interval::CalIntStatus;
 use crate::models::calendar_interval::CalIntStatus::Claimable;
+use crate::models::goal::Placement;
 use crate::models::interval::Interval;
 use crate::models::{activity::Activity, calendar::Calendar};
+use chrono::Timelike;
 use std::cmp::{max, min};
+use std::collections::HashSet;
 use std::fmt::{Debug, Formatter};
 
 struct LeastConflict {
     start: usize,
     end: usize,
     claims: usize,
+    hours_outside_preference: usize,
+    hours_from_anchor: usize,
 }
 
 impl Debug for LeastConflict {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let _ = writeln!(
             f,
-            "{:?} claims on {:?}-{:?} ({:?}:00-{:?}:00) day {:?}",
+            "{:?} claims, {:?} hours outside preference, {:?} hours from anchor on {:?}-{:?} ({:?}:00-{:?}:00) day {:?}",
             self.claims,
+            self.hours_outside_preference,
+            self.hours_from_anchor,
             self.start,
             self.end,
             self.start % 24,
@@ -36,10 +43,15 @@ pub(crate) fn place(calendar: &mut Calendar, activities: &mut [Activity]) {
     println!("Starting placing...");
     //Todo first check if there are any tasks_done_today
     calendar.register_activities(activities);
+    calendar.prepare_day_constraints(activities);
     dbg!(&calendar);
     postpone(calendar, activities);
 
-    while let Some(act_index) = find_next_act_index(calendar, activities) {
+    //activities that need their compatible intervals checked against budgets again
+    //budgets may have changed since a previous call to place, so check all of them once
+    let mut budget_check_needed: HashSet<usize> = (0..activities.len()).collect();
+    while let Some(act_index) = find_next_act_index(calendar, activities, &mut budget_check_needed)
+    {
         println!(
             "Found activity {} to schedule, act_index {}",
             activities[act_index].title, act_index,
@@ -63,12 +75,22 @@ pub(crate) fn place(calendar: &mut Calendar, activities: &mut [Activity]) {
                     end: least_conflict_position.end,
                 };
                 calendar.register(&interval_to_use, act_index);
-                if calendar.is_participating_in_a_budget(&activities[act_index].goal_id) {
+                if calendar.has_time_budgets_for(&activities[act_index].goal_id) {
                     calendar.reduce_budgets_for(
                         &activities[act_index].goal_id,
                         interval_to_use.start,
                         interval_to_use.end,
                     );
+                    let impacted_goals =
+                        calendar.get_goals_sharing_budget_with(&activities[act_index].goal_id);
+                    for (index, activity) in activities.iter().enumerate() {
+                        //workload limits are shared by all goals
+                        if !calendar.workload_budgets.is_empty()
+                            || impacted_goals.contains(&activity.goal_id)
+                        {
+                            budget_check_needed.insert(index);
+                        }
+                    }
                 }
                 //Adjust activity internals
                 //Todo: Simplify mess below
@@ -78,7 +100,6 @@ pub(crate) fn place(calendar: &mut Calendar, activities: &mut [Activity]) {
                             interval_to_use.end - interval_to_use.start;
                         if activities[act_index].duration_left == 0 {
                             activities[act_index].status = Scheduled; //all at once, not per hour scheduling like before
-                            activities[act_index].reset_compatible_intervals();
                         }
                     }
                     GetToMinDayBudget => {
@@ -86,7 +107,6 @@ pub(crate) fn place(calendar: &mut Calendar, activities: &mut [Activity]) {
                             interval_to_use.end - interval_to_use.start;
                         if activities[act_index].duration_left == 0 {
                             activities[act_index].status = Scheduled;
-                            activities[act_index].reset_compatible_intervals();
                         }
                     }
                     GetToMinWeekBudget => {
@@ -95,7 +115,6 @@ pub(crate) fn place(calendar: &mut Calendar, activities: &mut [Activity]) {
                             interval_to_use.end - interval_to_use.start;
                         if activities[act_index].duration_left == 0 {
                             activities[act_index].status = Scheduled;
-                            activities[act_index].reset_compatible_intervals();
                         }
                     }
                     TopUpWeekBudget => {
@@ -106,25 +125,34 @@ pub(crate) fn place(calendar: &mut Calendar, activities: &mut [Activity]) {
                                 .participating_goals
                                 .contains(&activities[act_index].goal_id)
                             {
+                                //only the week the hours went into can be full now
                                 for time_budget in &budget.time_budgets {
                                     if time_budget.time_budget_type == Week
+                                        && time_budget.calendar_start_index <= interval_to_use.start
+                                        && interval_to_use.start < time_budget.calendar_end_index
                                         && time_budget.max_scheduled == time_budget.scheduled
                                     {
                                         activities[act_index].status = Scheduled;
-                                        activities[act_index].reset_compatible_intervals();
                                     }
                                 }
                             }
                         }
                         if activities[act_index].duration_left == 0 {
                             activities[act_index].status = Scheduled;
-                            activities[act_index].reset_compatible_intervals();
                         }
                     }
                 }
                 //Now we know if the activity has been scheduled - even if it is a budget_min_week
                 //This helps us in de decision to let go of other claims inside occupy function
+                //occupy also resets the intervals of a scheduled activity, once it let go of its claims there
                 calendar.occupy(&interval_to_use, act_index, activities);
+                let goal_id = activities[act_index].goal_id.clone();
+                calendar.set_anchor_hour_for(&goal_id, interval_to_use.start);
+                calendar.apply_day_constraints(
+                    &goal_id,
+                    &Calendar::get_days_of(&interval_to_use),
+                    activities,
+                );
             }
         }
         println!("Finding next activity to schedule...");
@@ -152,63 +180,65 @@ fn postpone(calendar: &mut Calendar, activities: &mut [Activity]) {
     }
 }
 
-fn find_next_act_index(calendar: &mut Calendar, activities: &mut [Activity]) -> Option<usize> {
-    //check budget validity for all positions of all cal_ints - for all activities
+fn find_next_act_index(
+    calendar: &mut Calendar,
+    activities: &mut [Activity],
+    budget_check_needed: &mut HashSet<usize>,
+) -> Option<usize> {
+    //check budget validity for all positions of all cal_ints
     //since last activity placed might put some positions over the max / day or max/week
-    // todo later as possible optimization (measure/reason if useful!): not all activities have to be checked, just the ones that share the same budget(s)
-    for activity in activities.iter_mut() {
-        calendar.update_compatible_intervals(activity);
+    //only activities sharing a budget with it can be impacted - checking others again would change nothing
+    for act_index in budget_check_needed.drain() {
+        calendar.update_compatible_intervals(&mut activities[act_index]);
     }
 
     let mut highest_flex = 0;
     let mut act_index_next_to_schedule: Option<usize> = None;
 
-    for (act_index, activity) in activities.iter_mut().enumerate() {
-        if !(activity.status == Unprocessed || activity.status == BestEffort)
-        //only get Flex for Unprocessed or BestEffort
-        {
-            continue;
-        };
-        println!(
-            "Getting flex for {}, act_index {}.",
-            activity.title, act_index
-        );
+    //activities with a soft deadline can be late, so they only go once no other activity can
+    for soft_deadlines in [false, true] {
+        for (act_index, activity) in activities.iter_mut().enumerate() {
+            if !(activity.status == Unprocessed || activity.status == BestEffort)
+                || activity.has_soft_deadline != soft_deadlines
+            //only get Flex for Unprocessed or BestEffort
+            {
+                continue;
+            };
+            println!(
+                "Getting flex for {}, act_index {}.",
+                activity.title, act_index
+            );
 
-        let flex = activity.flex();
-        match flex {
-            0 => {
-                //no place possible
-                activity.mark_impossible();
-            }
-            1 => {
-                //only one place possible => need to fix_on_calendar
-                println!("Flex of 1 found for activity {}", activity.title);
-                act_index_next_to_schedule = Some(act_index);
-                break;
-            }
-            _ => {
-                if flex > highest_flex {
-                    highest_flex = flex;
+            let flex = activity.flex();
+            match flex {
+                0 => {
+                    //no place possible
+                    activity.mark_impossible();
+                }
+                1 => {
+                    //only one place possible => need to fix_on_calendar
+                    println!("Flex of 1 found for activity {}", activity.title);
                     act_index_next_to_schedule = Some(act_index);
+                    break;
+                }
+                _ => {
+                    if flex > highest_flex {
+                        highest_flex = flex;
+                        act_index_next_to_schedule = Some(act_index);
+                    }
                 }
             }
         }
+        if act_index_next_to_schedule.is_some() {
+            break;
+        }
     }
     act_index_next_to_schedule
 }
 
 fn get_conflicts_for(calendar: &Calendar, start_index: usize, end_index: usize) -> usize {
     let mut conflicts: Option<usize> = None;
-    for cal_int in &calendar.intervals {
-        if end_index <= cal_int.interval.start {
-            //no more possible overlaps
-            return conflicts.expect("When calling get conflicts a result is expected");
-        }
-        if start_index >= cal_int.interval.end {
-            //no overlap yet
-            continue;
-        }
-        //overlap
+    for cal_int in calendar.intervals.overlapping(start_index, end_index) {
         let overlap_start = max(start_index, cal_int.interval.start);
         let overlap_end = min(end_index, cal_int.interval.end);
         match &cal_int.status {
@@ -229,8 +259,15 @@ fn get_conflicts_for(calendar: &Calendar, start_index: usize, end_index: usize)
 fn get_best_index_for(calendar: &Calendar, activity: &Activity) -> Option<LeastConflict> {
     let mut least_conflict: Option<LeastConflict> = None;
 
+    //positions are tried from the earliest on - or from the latest on, so ties go to the latest
+    let is_alap = activity.placement == Placement::Alap;
+    let anchor_hour = calendar.get_anchor_hour_for(&activity.goal_id);
+    let mut intervals: Vec<&Interval> = activity.compatible_intervals.iter().collect();
+    if is_alap {
+        intervals.reverse();
+    }
     //the activity intervals can cover multiple calendar intervals as the activity interval doesn't get fragmented
-    for interval in &activity.compatible_intervals {
+    for interval in intervals {
         let interval_len = interval.end - interval.start;
         let max_inner_offset = interval_len - activity.min_block_size;
         #[cfg(debug_assertions)]
@@ -238,12 +275,31 @@ fn get_best_index_for(calendar: &Calendar, activity: &Activity) -> Option<LeastC
             interval_len >= activity.min_block_size,
             "Length of compatible activity interval should be >= min_block_size"
         );
-        for inner_offset in 0..=max_inner_offset {
+        for step in 0..=max_inner_offset {
+            let inner_offset = if is_alap {
+                max_inner_offset - step
+            } else {
+                step
+            };
             let new_conflicts = get_conflicts_for(
                 calendar,
                 interval.start + inner_offset,
                 interval.start + inner_offset + activity.min_block_size,
             );
+            //preferred windows are soft: a position inside them beats one with less conflicts
+            let new_hours_outside_preference = activity.hours_outside_preference(
+                calendar,
+                interval.start + inner_offset,
+                interval.start + inner_offset + activity.min_block_size,
+            );
+            //a budget with consistent time prefers the hour of the day it started at - after the preferred windows
+            let new_hours_from_anchor = anchor_hour.map_or(0, |anchor_hour| {
+                let hour = calendar
+                    .get_datetime_of(interval.start + inner_offset)
+                    .hour() as usize;
+                let difference = hour.abs_diff(anchor_hour);
+                min(difference, 24 - difference)
+            });
             //Todo Check if budget allows it - if not continue
             match least_conflict {
                 None => {
@@ -251,16 +307,31 @@ fn get_best_index_for(calendar: &Calendar, activity: &Activity) -> Option<LeastC
                         start: interval.start + inner_offset,
                         end: interval.start + inner_offset + activity.min_block_size,
                         claims: new_conflicts,
+                        hours_outside_preference: new_hours_outside_preference,
+                        hours_from_anchor: new_hours_from_anchor,
                     });
                 }
                 Some(ref mut least_conflict) => {
-                    if new_conflicts < least_conflict.claims {
+                    if (
+                        new_hours_outside_preference,
+                        new_hours_from_anchor,
+                        new_conflicts,
+                    ) < (
+                        least_conflict.hours_outside_preference,
+                        least_conflict.hours_from_anchor,
+                        least_conflict.claims,
+                    ) {
                         least_conflict.start = interval.start + inner_offset;
                         least_conflict.end =
                             interval.start + inner_offset + activity.min_block_size;
                         least_conflict.claims = new_conflicts;
+                        least_conflict.hours_outside_preference = new_hours_outside_preference;
+                        least_conflict.hours_from_anchor = new_hours_from_anchor;
                     }
-                    if new_conflicts == 1 {
+                    if new_conflicts == 1
+                        && new_hours_outside_preference == 0
+                        && new_hours_from_anchor == 0
+                    {
                         break;
                     }
                 }
@@ -279,6 +350,7 @@ pub(crate) fn place_postponed_as_best_effort(calendar: &mut Calendar, activities
                 activity.title
             );
             activity.status = BestEffort;
+            activity.is_best_effort = true;
         }
     }
     place(calendar, activities);
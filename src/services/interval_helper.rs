@@ -64,6 +64,35 @@ pub(crate) fn get_compatible_intervals(
     start: NaiveDateTime,
     end: NaiveDateTime,
     not_on: &Option<Vec<Slot>>,
+) -> Vec<Interval> {
+    let mut result = get_intervals_in_filter(calendar, filter, start, end, not_on);
+
+    //remove anything that is alreeady occupied
+    let mut intervals_to_remove2: Vec<Interval> = vec![];
+    for cal_interval in calendar
+        .intervals
+        .overlapping(calendar.get_index_of(start), calendar.get_index_of(end))
+    {
+        match cal_interval.status {
+            CalIntStatus::Claimable(_) => {}
+            CalIntStatus::Occupied(_, _) => intervals_to_remove2.push(Interval {
+                start: cal_interval.interval.start,
+                end: cal_interval.interval.end,
+            }),
+        }
+    }
+    result = subtract_intervals(result, &intervals_to_remove2);
+
+    result
+}
+
+/// The intervals between start and end inside the filter and outside not_on - occupied or not
+pub(crate) fn get_intervals_in_filter(
+    calendar: &Calendar,
+    filter: Option<&Filter>,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    not_on: &Option<Vec<Slot>>,
 ) -> Vec<Interval> {
     let mut result = vec![Interval {
         start: calendar.get_index_of(start),
@@ -91,19 +120,14 @@ pub(crate) fn get_compatible_intervals(
 
         while current <= end {
             if filter.on_days.contains(&current.weekday()) {
-                if filter.after_time < filter.before_time {
-                    intervals_to_remove.push(Interval {
-                        start: current_index_offset,
-                        end: current_index_offset + filter.after_time,
-                    });
-                    intervals_to_remove.push(Interval {
-                        start: current_index_offset + filter.before_time,
-                        end: current_index_offset + 24,
-                    })
-                } else {
+                //hours of the day outside all windows of the filter for that day
+                let hours_outside_filter: Vec<bool> = (0..24)
+                    .map(|hour_of_day| !filter.contains_hour(current.weekday(), hour_of_day))
+                    .collect();
+                for interval in reduce(&hours_outside_filter) {
                     intervals_to_remove.push(Interval {
-                        start: current_index_offset + filter.before_time,
-                        end: current_index_offset + filter.after_time,
+                        start: current_index_offset + interval.start,
+                        end: current_index_offset + interval.end,
                     });
                 }
             } else {
@@ -116,20 +140,5 @@ pub(crate) fn get_compatible_intervals(
             current_index_offset += 24;
         }
     }
-    result = subtract_intervals(result, &intervals_to_remove);
-
-    //remove anything that is alreeady occupied
-    let mut intervals_to_remove2: Vec<Interval> = vec![];
-    for cal_interval in &calendar.intervals {
-        match cal_interval.status {
-            CalIntStatus::Claimable(_) => {}
-            CalIntStatus::Occupied(_, _) => intervals_to_remove2.push(Interval {
-                start: cal_interval.interval.start,
-                end: cal_interval.interval.end,
-            }),
-        }
-    }
-    result = subtract_intervals(result, &intervals_to_remove2);
-
-    result
+    subtract_intervals(result, &intervals_to_remove)
 }
@@ -1,3 +1,4 @@
 pub mod activity_generator;
 pub mod activity_placer;
 pub mod interval_helper;
+pub mod verifier;
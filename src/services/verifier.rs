@@ -0,0 +1,673 @@
+//! Independent check of a schedule against the constraints of its input.
+//! Deliberately doesn't use Calendar, Activity or Budget so that it keeps working as a
+//! safety net when the placer changes, and can validate schedules edited by hand.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Add;
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Timelike};
+use serde::Serialize;
+
+use crate::models::goal::{DayRelation, DeadlineMode, Filter, Goal};
+use crate::models::task::{FinalTasks, Task};
+use crate::technical::input_output::Input;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum Violation {
+    UnknownGoal {
+        taskid: usize,
+        goalid: String,
+    },
+    Overlap {
+        taskid: usize,
+        other_taskid: usize,
+    },
+    OutsideFilter {
+        taskid: usize,
+        goalid: String,
+        hour: NaiveDateTime,
+    },
+    NotOnDay {
+        taskid: usize,
+        goalid: String,
+        hour: NaiveDateTime,
+    },
+    InNotOn {
+        taskid: usize,
+        goalid: String,
+        hour: NaiveDateTime,
+    },
+    BeforeStart {
+        taskid: usize,
+        goalid: String,
+    },
+    AfterDeadline {
+        taskid: usize,
+        goalid: String,
+    },
+    BudgetMinNotMet {
+        goalid: String,
+        period_start_date_time: NaiveDateTime,
+        period_end_date_time: NaiveDateTime,
+        scheduled: usize,
+        min: usize,
+    },
+    BudgetMaxExceeded {
+        goalid: String,
+        period_start_date_time: NaiveDateTime,
+        period_end_date_time: NaiveDateTime,
+        scheduled: usize,
+        max: usize,
+    },
+    GoalMaxPerDayExceeded {
+        goalid: String,
+        period_start_date_time: NaiveDateTime,
+        period_end_date_time: NaiveDateTime,
+        scheduled: usize,
+        max: usize,
+    },
+    DayConstraintBroken {
+        goalid: String,
+        other_goalid: String,
+        relation: DayRelation,
+        day: NaiveDate,
+    },
+    CompletedTaskNotPreserved {
+        goalid: String,
+        start: NaiveDateTime,
+        deadline: NaiveDateTime,
+    },
+    BeforeNow {
+        taskid: usize,
+        goalid: String,
+    },
+    TagCapExceeded {
+        tag: String,
+        period_start_date_time: NaiveDateTime,
+        period_end_date_time: NaiveDateTime,
+        scheduled: usize,
+        max: usize,
+    },
+    MaxHoursPerDayExceeded {
+        period_start_date_time: NaiveDateTime,
+        period_end_date_time: NaiveDateTime,
+        scheduled: usize,
+        max: usize,
+    },
+    ReservedFreeTimeTaken {
+        period_start_date_time: NaiveDateTime,
+        period_end_date_time: NaiveDateTime,
+        free: usize,
+        reserved: usize,
+    },
+}
+
+/// Checks every task in output against the goals, budgets and tasks completed today in input.
+/// An empty result means the schedule is valid.
+pub fn verify(input: &Input, output: &FinalTasks) -> Vec<Violation> {
+    let goals: BTreeMap<&str, &Goal> = input
+        .goals
+        .iter()
+        .map(|goal| (goal.id.as_str(), goal))
+        .collect();
+    let tasks: Vec<&Task> = output
+        .scheduled
+        .iter()
+        .flat_map(|day_tasks| day_tasks.tasks.iter())
+        .filter(|task| !["free", "blocked", "past"].contains(&task.goalid.as_str()))
+        .collect();
+
+    let mut violations = vec![];
+    check_overlaps(&tasks, &mut violations);
+    check_goal_constraints(&goals, &tasks, &mut violations);
+    check_budgets(input, &goals, &tasks, &mut violations);
+    check_goal_max_per_day(input, &goals, &tasks, &mut violations);
+    check_day_constraints(&goals, &tasks, &mut violations);
+    check_tag_caps(input, &goals, &tasks, &mut violations);
+    check_workload_limits(input, &tasks, &mut violations);
+    check_completed_today(input, &tasks, &mut violations);
+    check_before_now(input, &tasks, &mut violations);
+    violations
+}
+
+/// The first full hour at or after now - an hour that has already started is in the past
+fn get_now_hour(input: &Input) -> Option<NaiveDateTime> {
+    input.now.map(|now| {
+        let now_hour = now.with_minute(0).unwrap().with_second(0).unwrap();
+        if now_hour < now {
+            now_hour.add(Duration::hours(1))
+        } else {
+            now_hour
+        }
+    })
+}
+
+/// The days of the calendar, from midnight
+fn get_days_of(input: &Input) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+    let mut days = vec![];
+    let mut day_start = input.start_date;
+    while day_start < input.end_date {
+        days.push((day_start, day_start.add(Duration::days(1))));
+        day_start = day_start.add(Duration::days(1));
+    }
+    days
+}
+
+/// The weeks of the calendar, starting on the week start - cut off at the start and end of the calendar
+fn get_weeks_of(input: &Input) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+    let mut week_start = input.start_date;
+    if let Some(first_day) = input.week_start.map(|week_start| week_start.first_day()) {
+        let days_since_week_start = (7 + input.start_date.weekday().num_days_from_monday()
+            - first_day.num_days_from_monday())
+            % 7;
+        week_start = week_start.add(Duration::days(-(days_since_week_start as i64)));
+    }
+    let mut weeks = vec![];
+    while week_start < input.end_date {
+        let week_end = week_start.add(Duration::days(7));
+        weeks.push((
+            week_start.max(input.start_date),
+            week_end.min(input.end_date),
+        ));
+        week_start = week_end;
+    }
+    weeks
+}
+
+fn hours_of(task: &Task) -> impl Iterator<Item = NaiveDateTime> + '_ {
+    (0..task.duration as i64).map(move |offset| task.start.add(Duration::hours(offset)))
+}
+
+fn check_overlaps(tasks: &[&Task], violations: &mut Vec<Violation>) {
+    let mut sorted = tasks.to_vec();
+    sorted.sort_by_key(|task| task.start);
+    for (index, task) in sorted.iter().enumerate() {
+        for other in &sorted[index + 1..] {
+            if other.start >= task.deadline {
+                break;
+            }
+            violations.push(Violation::Overlap {
+                taskid: task.taskid,
+                other_taskid: other.taskid,
+            });
+        }
+    }
+}
+
+/// All budget goals the goal counts toward, including itself if it is a budget
+fn get_budgets_of<'a>(goal_id: &str, goals: &BTreeMap<&str, &'a Goal>) -> Vec<&'a Goal> {
+    goals
+        .values()
+        .filter(|goal| goal.budget_config.is_some())
+        .filter(|budget| get_descendants_of(budget, goals).contains(&goal_id.to_string()))
+        .copied()
+        .collect()
+}
+
+/// Ids of the goal itself and all goals below it
+fn get_descendants_of(goal: &Goal, goals: &BTreeMap<&str, &Goal>) -> Vec<String> {
+    let mut descendants = vec![goal.id.clone()];
+    let mut index = 0;
+    while index < descendants.len() {
+        if let Some(children) = goals
+            .get(descendants[index].as_str())
+            .and_then(|goal| goal.children.as_ref())
+        {
+            for child in children {
+                if !descendants.contains(child) {
+                    descendants.push(child.clone());
+                }
+            }
+        }
+        index += 1;
+    }
+    descendants
+}
+
+fn is_in_time_of_day(filter: &Filter, hour: NaiveDateTime) -> bool {
+    filter.contains_hour(hour.weekday(), hour.hour() as usize)
+}
+
+fn check_goal_constraints(
+    goals: &BTreeMap<&str, &Goal>,
+    tasks: &[&Task],
+    violations: &mut Vec<Violation>,
+) {
+    for task in tasks {
+        let Some(goal) = goals.get(task.goalid.as_str()) else {
+            violations.push(Violation::UnknownGoal {
+                taskid: task.taskid,
+                goalid: task.goalid.clone(),
+            });
+            continue;
+        };
+
+        //goals in a budget use the filters of the budget
+        let budgets = get_budgets_of(&goal.id, goals);
+        let filters: Vec<&Filter> = if budgets.is_empty() {
+            goal.filters.iter().collect()
+        } else {
+            budgets
+                .iter()
+                .filter_map(|budget| budget.filters.as_ref())
+                .collect()
+        };
+
+        for hour in hours_of(task) {
+            if filters
+                .iter()
+                .any(|filter| !filter.on_days.contains(&hour.weekday()))
+            {
+                violations.push(Violation::NotOnDay {
+                    taskid: task.taskid,
+                    goalid: goal.id.clone(),
+                    hour,
+                });
+            } else if filters
+                .iter()
+                .any(|filter| !is_in_time_of_day(filter, hour))
+            {
+                violations.push(Violation::OutsideFilter {
+                    taskid: task.taskid,
+                    goalid: goal.id.clone(),
+                    hour,
+                });
+            }
+            if let Some(not_on) = &goal.not_on {
+                if not_on
+                    .iter()
+                    .any(|slot| hour >= slot.start && hour < slot.end)
+                {
+                    violations.push(Violation::InNotOn {
+                        taskid: task.taskid,
+                        goalid: goal.id.clone(),
+                        hour,
+                    });
+                }
+            }
+        }
+
+        if task.start < goal.start {
+            violations.push(Violation::BeforeStart {
+                taskid: task.taskid,
+                goalid: goal.id.clone(),
+            });
+        }
+        if let Some(deadline) = goal.deadline {
+            //a soft deadline may be missed - the task then reports how late it is
+            if task.deadline > deadline && goal.deadline_mode == DeadlineMode::Hard {
+                violations.push(Violation::AfterDeadline {
+                    taskid: task.taskid,
+                    goalid: goal.id.clone(),
+                });
+            }
+        }
+    }
+}
+
+fn check_budgets(
+    input: &Input,
+    goals: &BTreeMap<&str, &Goal>,
+    tasks: &[&Task],
+    violations: &mut Vec<Violation>,
+) {
+    for budget in goals.values() {
+        let Some(config) = &budget.budget_config else {
+            continue;
+        };
+        let participating_goals = get_descendants_of(budget, goals);
+        let budget_hours: Vec<NaiveDateTime> = tasks
+            .iter()
+            .filter(|task| participating_goals.contains(&task.goalid))
+            .flat_map(|task| hours_of(task))
+            .collect();
+        let scheduled_between = |start: NaiveDateTime, end: NaiveDateTime| {
+            budget_hours
+                .iter()
+                .filter(|hour| **hour >= start && **hour < end)
+                .count()
+        };
+
+        //a day of a budget can start later than midnight, like a night of sleep
+        let get_day_start = |date: NaiveDate| {
+            let day_start_hour = budget
+                .filters
+                .as_ref()
+                .map_or(0, |filter| filter.get_day_start_hour(date.weekday()));
+            date.and_hms_opt(0, 0, 0)
+                .unwrap()
+                .add(Duration::hours(day_start_hour as i64))
+        };
+        //a budget only counts between the start and deadline of its goal
+        let budget_end = budget.deadline.unwrap_or(NaiveDateTime::MAX);
+        let is_cut_by_goal =
+            |start: NaiveDateTime, end: NaiveDateTime| budget.start > start || budget_end < end;
+        let mut date = input.start_date.date().pred_opt().unwrap();
+        while get_day_start(date) < input.end_date {
+            let day_start = get_day_start(date);
+            let day_end = get_day_start(date.succ_opt().unwrap());
+            date = date.succ_opt().unwrap();
+            if day_end <= input.start_date {
+                continue;
+            }
+            let (mut min, mut max) = (
+                config.min_on(day_start.weekday()),
+                config.max_on(day_start.weekday()),
+            );
+            if let Some(filter) = &budget.filters {
+                if !filter.on_days.contains(&day_start.weekday()) {
+                    (min, max) = (0, 0);
+                }
+                //a day can't be expected to get more hours than its windows allow
+                let (start, end) = (day_start.max(budget.start), day_end.min(budget_end));
+                let hours_in_windows = if start < end {
+                    filter.hours_between(start, end)
+                } else {
+                    0
+                };
+                (min, max) = (min.min(hours_in_windows), max.min(hours_in_windows));
+                //nor more than the hours of its filter left after now
+                if let Some(now_hour) = get_now_hour(input).filter(|now| *now > day_start) {
+                    min = min.min(filter.hours_between(now_hour.max(day_start), day_end));
+                }
+            }
+            let scheduled = scheduled_between(day_start, day_end);
+            //days running over the edge of the calendar can't be expected to reach min
+            let is_complete = day_start >= input.start_date && day_end <= input.end_date;
+            check_budget_window(
+                &budget.id,
+                (day_start, day_end),
+                scheduled,
+                if is_complete { min } else { 0 },
+                max,
+                violations,
+            );
+        }
+
+        //hours spent before the calendar count toward the first week, up to its max
+        let spent: usize = input
+            .hours_spent
+            .iter()
+            .filter(|spent| participating_goals.contains(&spent.goalid))
+            .map(|spent| spent.hours)
+            .sum();
+        for (week_start, week_end) in get_weeks_of(input) {
+            let mut scheduled = scheduled_between(week_start, week_end);
+            if week_start == input.start_date {
+                scheduled += spent.min(config.max_per_week);
+            }
+            //partial weeks at the edges of the calendar can't be expected to reach min,
+            //nor weeks cut by the start or deadline of the goal
+            let is_complete =
+                week_end - week_start == Duration::days(7) && !is_cut_by_goal(week_start, week_end);
+            check_budget_window(
+                &budget.id,
+                (week_start, week_end),
+                scheduled,
+                if is_complete { config.min_per_week } else { 0 },
+                config.max_per_week,
+                violations,
+            );
+        }
+    }
+}
+
+fn check_budget_window(
+    goal_id: &str,
+    (period_start_date_time, period_end_date_time): (NaiveDateTime, NaiveDateTime),
+    scheduled: usize,
+    min: usize,
+    max: usize,
+    violations: &mut Vec<Violation>,
+) {
+    if scheduled < min {
+        violations.push(Violation::BudgetMinNotMet {
+            goalid: goal_id.to_string(),
+            period_start_date_time,
+            period_end_date_time,
+            scheduled,
+            min,
+        });
+    }
+    if scheduled > max {
+        violations.push(Violation::BudgetMaxExceeded {
+            goalid: goal_id.to_string(),
+            period_start_date_time,
+            period_end_date_time,
+            scheduled,
+            max,
+        });
+    }
+}
+
+/// Goals without a budget stay under their own max per day
+fn check_goal_max_per_day(
+    input: &Input,
+    goals: &BTreeMap<&str, &Goal>,
+    tasks: &[&Task],
+    violations: &mut Vec<Violation>,
+) {
+    for goal in goals.values().filter(|goal| goal.budget_config.is_none()) {
+        let Some(max) = goal.max_per_day else {
+            continue;
+        };
+        for (day_start, day_end) in get_days_of(input) {
+            let scheduled = tasks
+                .iter()
+                .filter(|task| task.goalid == goal.id)
+                .flat_map(|task| hours_of(task))
+                .filter(|hour| *hour >= day_start && *hour < day_end)
+                .count();
+            if scheduled > max {
+                violations.push(Violation::GoalMaxPerDayExceeded {
+                    goalid: goal.id.clone(),
+                    period_start_date_time: day_start,
+                    period_end_date_time: day_end,
+                    scheduled,
+                    max,
+                });
+            }
+        }
+    }
+}
+
+/// Goals with a day constraint share a day with the other goal, or stay off its days or the days next to them
+fn check_day_constraints(
+    goals: &BTreeMap<&str, &Goal>,
+    tasks: &[&Task],
+    violations: &mut Vec<Violation>,
+) {
+    let days_of = |goal_id: &str| -> BTreeSet<NaiveDate> {
+        tasks
+            .iter()
+            .filter(|task| task.goalid == goal_id)
+            .flat_map(|task| hours_of(task))
+            .map(|hour| hour.date())
+            .collect()
+    };
+    for goal in goals.values() {
+        for constraint in &goal.day_constraints {
+            if !goals.contains_key(constraint.goalid.as_str()) {
+                continue;
+            }
+            let days = days_of(&goal.id);
+            let other_days = days_of(&constraint.goalid);
+            let broken_on: Vec<NaiveDate> = match constraint.relation {
+                DayRelation::SameDay => {
+                    if days.is_empty() || other_days.is_empty() || !days.is_disjoint(&other_days) {
+                        vec![]
+                    } else {
+                        days.iter().take(1).copied().collect()
+                    }
+                }
+                DayRelation::NotSameDay => {
+                    if goal.id == constraint.goalid {
+                        vec![]
+                    } else {
+                        days.intersection(&other_days).copied().collect()
+                    }
+                }
+                DayRelation::NotConsecutiveDays => days
+                    .iter()
+                    .filter(|day| {
+                        other_days.contains(&day.add(Duration::days(1)))
+                            || other_days.contains(&day.add(Duration::days(-1)))
+                    })
+                    .copied()
+                    .collect(),
+            };
+            for day in broken_on {
+                violations.push(Violation::DayConstraintBroken {
+                    goalid: goal.id.clone(),
+                    other_goalid: constraint.goalid.clone(),
+                    relation: constraint.relation,
+                    day,
+                });
+            }
+        }
+    }
+}
+
+/// The goals with a capped tag, and the goals below them, together stay under the caps of the tag
+fn check_tag_caps(
+    input: &Input,
+    goals: &BTreeMap<&str, &Goal>,
+    tasks: &[&Task],
+    violations: &mut Vec<Violation>,
+) {
+    for tag_cap in &input.tag_caps {
+        let mut participating_goals: Vec<String> = vec![];
+        for goal in goals
+            .values()
+            .filter(|goal| goal.tags.contains(&tag_cap.tag))
+        {
+            participating_goals.extend(get_descendants_of(goal, goals));
+        }
+        let tag_hours: Vec<NaiveDateTime> = tasks
+            .iter()
+            .filter(|task| participating_goals.contains(&task.goalid))
+            .flat_map(|task| hours_of(task))
+            .collect();
+        let mut check_periods =
+            |max: Option<usize>, periods: Vec<(NaiveDateTime, NaiveDateTime)>| {
+                let Some(max) = max else {
+                    return;
+                };
+                for (period_start, period_end) in periods {
+                    let scheduled = tag_hours
+                        .iter()
+                        .filter(|hour| **hour >= period_start && **hour < period_end)
+                        .count();
+                    if scheduled > max {
+                        violations.push(Violation::TagCapExceeded {
+                            tag: tag_cap.tag.clone(),
+                            period_start_date_time: period_start,
+                            period_end_date_time: period_end,
+                            scheduled,
+                            max,
+                        });
+                    }
+                }
+            };
+        check_periods(tag_cap.max_per_day, get_days_of(input));
+        check_periods(tag_cap.max_per_week, get_weeks_of(input));
+    }
+}
+
+/// All tasks together stay under the max hours per day, and leave the reserved free time free
+fn check_workload_limits(input: &Input, tasks: &[&Task], violations: &mut Vec<Violation>) {
+    let scheduled_between = |start: NaiveDateTime, end: NaiveDateTime| {
+        tasks
+            .iter()
+            .flat_map(|task| hours_of(task))
+            .filter(|hour| *hour >= start && *hour < end)
+            .count()
+    };
+    if let Some(max) = input.max_hours_per_day {
+        let mut day_start = input.start_date;
+        while day_start < input.end_date {
+            let day_end = day_start.add(Duration::days(1));
+            let scheduled = scheduled_between(day_start, day_end);
+            if scheduled > max {
+                violations.push(Violation::MaxHoursPerDayExceeded {
+                    period_start_date_time: day_start,
+                    period_end_date_time: day_end,
+                    scheduled,
+                    max,
+                });
+            }
+            day_start = day_end;
+        }
+    }
+    for reserved in &input.reserved_free_time {
+        let window_hours = if reserved.after_time < reserved.before_time {
+            reserved.before_time - reserved.after_time
+        } else {
+            24 - reserved.after_time + reserved.before_time
+        };
+        //a window crossing midnight can start the day before the calendar
+        let mut day_start = input.start_date.add(Duration::days(-1));
+        while day_start < input.end_date {
+            let window_start = day_start.add(Duration::hours(reserved.after_time as i64));
+            let window_end = window_start.add(Duration::hours(window_hours as i64));
+            //overlapping tasks can count more hours than the window has
+            let scheduled = scheduled_between(window_start, window_end);
+            let free = window_hours.saturating_sub(scheduled);
+            if free < reserved.hours.min(window_hours) || scheduled > window_hours {
+                violations.push(Violation::ReservedFreeTimeTaken {
+                    period_start_date_time: window_start,
+                    period_end_date_time: window_end,
+                    free,
+                    reserved: reserved.hours,
+                });
+            }
+            day_start = day_start.add(Duration::days(1));
+        }
+    }
+}
+
+/// Only tasks completed today can be before now - checked against the input,
+/// not the completedToday flag of the output
+fn check_before_now(input: &Input, tasks: &[&Task], violations: &mut Vec<Violation>) {
+    let Some(now_hour) = get_now_hour(input) else {
+        return;
+    };
+    for task in tasks {
+        let is_completed_today = input.tasks_completed_today.iter().any(|completed| {
+            completed.goalid == task.goalid
+                && completed.start < task.deadline
+                && task.start < completed.deadline
+        });
+        if task.start < now_hour && !is_completed_today {
+            violations.push(Violation::BeforeNow {
+                taskid: task.taskid,
+                goalid: task.goalid.clone(),
+            });
+        }
+    }
+}
+
+fn check_completed_today(input: &Input, tasks: &[&Task], violations: &mut Vec<Violation>) {
+    for completed in &input.tasks_completed_today {
+        let mut hour = completed.start;
+        while hour < completed.deadline {
+            let is_covered = tasks.iter().any(|task| {
+                task.goalid == completed.goalid && hour >= task.start && hour < task.deadline
+            });
+            if !is_covered {
+                violations.push(Violation::CompletedTaskNotPreserved {
+                    goalid: completed.goalid.clone(),
+                    start: completed.start,
+                    deadline: completed.deadline,
+                });
+                break;
+            }
+            hour = hour.add(Duration::hours(1));
+        }
+    }
+}
@@ -1,6 +1,7 @@
+use crate::models::budget::{ReservedFreeTime, TagCap, WeekStart};
 use crate::models::goal::Goal;
 use crate::models::goal::Slot;
-use crate::models::task::TaskCompletedToday;
+use crate::models::task::{HoursSpent, MissedTask, TaskCompletedToday};
 use chrono::NaiveDateTime;
 use serde::Deserialize;
 use std::error::Error;
@@ -10,14 +11,38 @@ use std::io::prelude::*;
 use std::io::BufReader;
 use std::path::Path;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct Input {
     pub start_date: NaiveDateTime,
     pub end_date: NaiveDateTime,
     pub goals: Vec<Goal>,
     pub tasks_completed_today: Vec<TaskCompletedToday>,
+    /// Progress made on goals before the calendar starts
+    #[serde(default)]
+    pub hours_spent: Vec<HoursSpent>,
+    /// Tasks from earlier schedules that didn't get done
+    #[serde(default)]
+    pub missed_tasks: Vec<MissedTask>,
+    /// Max hours per day and/or week for all goals with a tag
+    #[serde(default)]
+    pub tag_caps: Vec<TagCap>,
+    /// Never schedule more than this many hours per day, for all goals together
+    #[serde(default)]
+    pub max_hours_per_day: Option<usize>,
+    /// Windows of every day that keep a number of hours free
+    #[serde(default)]
+    pub reserved_free_time: Vec<ReservedFreeTime>,
+    /// Day the weeks of budgets and tag caps start on - without it weeks start on the start date
+    #[serde(default)]
+    pub week_start: Option<WeekStart>,
     pub global_not_on: Option<Vec<Slot>>,
+    /// Hours before now can't be used anymore, except by tasks completed today
+    #[serde(default)]
+    pub now: Option<NaiveDateTime>,
+    /// Split free time into free and blocked segments, showing which goals could still use it
+    #[serde(default)]
+    pub free_time_segments: bool,
 }
 
 pub fn get_input_from_json<P: AsRef<Path>>(path: P) -> Result<Input, Box<dyn Error>> {
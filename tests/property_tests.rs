@@ -0,0 +1,1089 @@
+//! Property based tests for `run_scheduler_with_input`.
+//!
+//! Instead of comparing against a hand written expected.json, random goal trees are generated
+//! and the resulting schedule is checked against invariants that should hold for any input.
+//! Proptest shrinks a failing case to a minimal input before reporting it.
+
+extern crate scheduler;
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Timelike, Weekday};
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+
+use scheduler::models::budget::{ReservedFreeTime, TagCap, TimeBudgetType, WeekStart};
+use scheduler::models::goal::{
+    BudgetConfig, DayBudget, DayConstraint, DayRelation, DeadlineMode, Distribution, Filter, Goal,
+    Placement, Slot, TimeWindow,
+};
+use scheduler::models::task::{FinalTasks, HoursSpent, MissedTask, Task};
+use scheduler::run_scheduler_with_input;
+use scheduler::services::verifier::{verify, Violation};
+use scheduler::technical::input_output::Input;
+
+const CALENDAR_DAYS: i64 = 7;
+const TAGS: [&str; 2] = ["screen", "outdoor"];
+const ALL_DAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+#[derive(Debug, Clone)]
+struct TestInput {
+    start_date: NaiveDateTime,
+    end_date: NaiveDateTime,
+    goals: Vec<Goal>,
+    hours_spent: Vec<HoursSpent>,
+    missed_tasks: Vec<MissedTask>,
+    now: Option<NaiveDateTime>,
+    free_time_segments: bool,
+    tag_caps: Vec<TagCap>,
+    max_hours_per_day: Option<usize>,
+    reserved_free_time: Vec<ReservedFreeTime>,
+    week_start: Option<WeekStart>,
+}
+
+impl TestInput {
+    fn to_input(&self) -> Input {
+        Input {
+            start_date: self.start_date,
+            end_date: self.end_date,
+            goals: self.goals.clone(),
+            tasks_completed_today: vec![],
+            hours_spent: self.hours_spent.clone(),
+            missed_tasks: self.missed_tasks.clone(),
+            tag_caps: self.tag_caps.clone(),
+            max_hours_per_day: self.max_hours_per_day,
+            reserved_free_time: self.reserved_free_time.clone(),
+            week_start: self.week_start,
+            global_not_on: None,
+            now: self.now,
+            free_time_segments: self.free_time_segments,
+        }
+    }
+
+    /// Hours from the start of the week the calendar starts in, to the start of the calendar
+    fn hours_into_first_week(&self) -> i64 {
+        self.week_start.map_or(0, |week_start| {
+            let days = (7 + self.start_date.weekday().num_days_from_monday()
+                - week_start.first_day().num_days_from_monday())
+                % 7;
+            days as i64 * 24
+        })
+    }
+}
+
+fn arb_calendar_start() -> impl Strategy<Value = NaiveDateTime> {
+    (0..365u64).prop_map(|offset| {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            + Duration::days(offset as i64)
+    })
+}
+
+fn arb_on_days() -> impl Strategy<Value = Vec<Weekday>> {
+    prop::sample::subsequence(ALL_DAYS.to_vec(), 1..=7)
+}
+
+/// Daily window that doesn't cross midnight
+fn arb_window() -> impl Strategy<Value = TimeWindow> {
+    (0..24usize, 1..=8usize).prop_map(|(after, length)| TimeWindow {
+        after_time: after,
+        before_time: (after + length).min(24),
+    })
+}
+
+/// Windows for up to two specific days of the week
+fn arb_day_windows() -> impl Strategy<Value = HashMap<Weekday, Vec<TimeWindow>>> {
+    prop::collection::hash_map(
+        prop::sample::select(ALL_DAYS.to_vec()),
+        prop::collection::vec(arb_window(), 1..=2),
+        0..=2,
+    )
+}
+
+/// Filter with daily windows that don't cross midnight
+fn arb_filter() -> impl Strategy<Value = Filter> {
+    (
+        0..24usize,
+        1..=24usize,
+        arb_on_days(),
+        prop::collection::vec(arb_window(), 0..=2),
+        arb_day_windows(),
+    )
+        .prop_map(
+            |(after, length, on_days, extra_windows, day_windows)| Filter {
+                after_time: after,
+                before_time: (after + length).min(24),
+                on_days,
+                extra_windows,
+                day_windows,
+            },
+        )
+}
+
+/// Filter with a daily window that crosses midnight, like sleep
+fn arb_midnight_filter() -> impl Strategy<Value = Filter> {
+    (
+        13..24usize,
+        1..12usize,
+        arb_on_days(),
+        prop::collection::vec(arb_window(), 0..=1),
+        arb_day_windows(),
+    )
+        .prop_map(
+            |(after, before, on_days, extra_windows, day_windows)| Filter {
+                after_time: after,
+                before_time: before,
+                on_days,
+                extra_windows,
+                day_windows,
+            },
+        )
+}
+
+/// All daily windows of a filter as (after_time, before_time)
+fn windows_of(filter: &Filter) -> Vec<(usize, usize)> {
+    let mut windows = vec![(filter.after_time, filter.before_time)];
+    for window in &filter.extra_windows {
+        windows.push((window.after_time, window.before_time));
+    }
+    windows
+}
+
+/// Daily windows of a filter on a specific day of the week
+fn windows_on(filter: &Filter, weekday: Weekday) -> Vec<(usize, usize)> {
+    match filter.day_windows.get(&weekday) {
+        Some(windows) => windows
+            .iter()
+            .map(|window| (window.after_time, window.before_time))
+            .collect(),
+        None => windows_of(filter),
+    }
+}
+
+/// Start of the budget day on a date - a window crossing midnight that goes on from the day before
+/// starts the day at its after_time
+fn budget_day_start(filter: &Filter, date: NaiveDate) -> NaiveDateTime {
+    let after_time_crossing_midnight = |weekday: Weekday| {
+        windows_on(filter, weekday)
+            .into_iter()
+            .find(|(after, before)| after > before)
+            .filter(|_| filter.on_days.contains(&weekday))
+            .map(|(after, _)| after)
+    };
+    let hour = match (
+        after_time_crossing_midnight(date.weekday().pred()),
+        after_time_crossing_midnight(date.weekday()),
+    ) {
+        (Some(_), Some(after)) => after,
+        _ => 0,
+    };
+    date.and_hms_opt(0, 0, 0).unwrap() + Duration::hours(hour as i64)
+}
+
+fn is_in_window((after, before): (usize, usize), hour_of_day: usize) -> bool {
+    if after < before {
+        hour_of_day >= after && hour_of_day < before
+    } else {
+        hour_of_day >= after || hour_of_day < before
+    }
+}
+
+fn arb_not_on(cal_start: NaiveDateTime) -> impl Strategy<Value = Option<Vec<Slot>>> {
+    prop::option::of(prop::collection::vec(
+        (0..CALENDAR_DAYS * 24, 1..8i64).prop_map(move |(offset, length)| Slot {
+            start: cal_start + Duration::hours(offset),
+            end: cal_start + Duration::hours((offset + length).min(CALENDAR_DAYS * 24)),
+        }),
+        1..3,
+    ))
+}
+
+prop_compose! {
+    fn arb_day_constraint()(
+        goalid in prop::sample::select(vec!["simple-0", "simple-1", "budget-0"]),
+        relation in prop::sample::select(vec![
+            DayRelation::SameDay,
+            DayRelation::NotSameDay,
+            DayRelation::NotConsecutiveDays,
+        ]),
+    ) -> DayConstraint {
+        DayConstraint {
+            goalid: goalid.to_string(),
+            relation,
+        }
+    }
+}
+
+prop_compose! {
+    fn arb_simple_goal(cal_start: NaiveDateTime)(
+        min_duration in 1..=12usize,
+        start_offset in 0..CALENDAR_DAYS * 24 - 1,
+        deadline_offset in prop::option::of(1..(CALENDAR_DAYS + 3) * 24),
+        deadline_mode in prop_oneof![Just(DeadlineMode::Hard), Just(DeadlineMode::Soft)],
+        filters in prop::option::of(arb_filter()),
+        not_on in arb_not_on(cal_start),
+        preferred_windows in prop::collection::vec(arb_window(), 0..=1),
+        tags in arb_tags(),
+        distribution in prop::option::of(prop::sample::select(vec![
+            Distribution::Even,
+            Distribution::FrontLoad,
+            Distribution::BackLoad,
+        ])),
+        max_per_day in prop::option::of(1..=6usize),
+        placement in prop_oneof![Just(Placement::Asap), Just(Placement::Alap)],
+        day_constraints in prop::collection::vec(arb_day_constraint(), 0..=1),
+    ) -> Goal {
+        let start = cal_start + Duration::hours(start_offset);
+        let deadline = deadline_offset.map(|offset| {
+            // a deadline after the calendar leaves the hours of a distribution on those days for later
+            cal_start + Duration::hours(offset.max(start_offset + 1))
+        });
+        Goal {
+            id: String::new(),
+            start,
+            deadline,
+            deadline_mode,
+            budget_config: None,
+            filters,
+            min_duration: Some(min_duration),
+            title: String::new(),
+            children: None,
+            not_on,
+            preferred_windows,
+            tags,
+            distribution,
+            max_per_day,
+            placement,
+            day_constraints,
+        }
+    }
+}
+
+prop_compose! {
+    fn arb_reserved_free_time()(window in arb_window(), hours in 0..=3usize) -> ReservedFreeTime {
+        ReservedFreeTime {
+            after_time: window.after_time,
+            before_time: window.before_time,
+            hours,
+        }
+    }
+}
+
+fn arb_tags() -> impl Strategy<Value = Vec<String>> {
+    prop::collection::vec(prop::sample::select(TAGS.to_vec()), 0..=1)
+        .prop_map(|tags| tags.into_iter().map(String::from).collect())
+}
+
+prop_compose! {
+    fn arb_tag_cap()(
+        tag in prop::sample::select(TAGS.to_vec()),
+        max_per_day in prop::option::of(0..=6usize),
+        max_per_week in prop::option::of(0..=20usize),
+    ) -> TagCap {
+        TagCap {
+            tag: tag.to_string(),
+            max_per_day,
+            max_per_week,
+        }
+    }
+}
+
+/// Min and max overrides for up to two specific days of the week
+fn arb_day_budgets() -> impl Strategy<Value = HashMap<Weekday, DayBudget>> {
+    prop::collection::hash_map(
+        prop::sample::select(ALL_DAYS.to_vec()),
+        (prop::option::of(0..=4usize), prop::option::of(0..=8usize))
+            .prop_map(|(min, max)| DayBudget { min, max }),
+        0..=2,
+    )
+}
+
+prop_compose! {
+    fn arb_budget_config(filter: Filter)(
+        min_per_day in 0..=3usize,
+        extra_per_day in 0..=4usize,
+        extra_min_per_week in 0..=5usize,
+        extra_max_per_week in 0..=10usize,
+        day_budgets in arb_day_budgets(),
+        consistent_time in any::<bool>(),
+    ) -> BudgetConfig {
+        let window = (0..24)
+            .filter(|hour_of_day| {
+                windows_of(&filter)
+                    .into_iter()
+                    .any(|window| is_in_window(window, *hour_of_day))
+            })
+            .count();
+        let min_per_day = min_per_day.min(window);
+        let max_per_day = (min_per_day + extra_per_day).min(window);
+        // an override can't make the min of a day higher than its max
+        let day_budgets = day_budgets
+            .into_iter()
+            .map(|(weekday, DayBudget { min, max })| {
+                let min_on_day = min.unwrap_or(min_per_day);
+                let max = match max {
+                    Some(max) => Some(max.max(min_on_day)),
+                    None if min_on_day > max_per_day => Some(min_on_day),
+                    None => None,
+                };
+                (weekday, DayBudget { min, max })
+            })
+            .collect();
+        let mut config = BudgetConfig {
+            min_per_day,
+            max_per_day,
+            min_per_week: 0,
+            max_per_week: 0,
+            day_budgets,
+            consistent_time,
+        };
+        let min_per_week = filter
+            .on_days
+            .iter()
+            .map(|weekday| config.min_on(*weekday))
+            .sum::<usize>()
+            + extra_min_per_week;
+        let max_on_any_day = filter
+            .on_days
+            .iter()
+            .map(|weekday| config.max_on(*weekday))
+            .max()
+            .unwrap_or(0);
+        config.min_per_week = min_per_week;
+        config.max_per_week =
+            (max_on_any_day * filter.on_days.len()).max(min_per_week) + extra_max_per_week;
+        config
+    }
+}
+
+/// A budget goal, optionally with a simple child goal that inherits its filters
+fn arb_budget_goal(cal_start: NaiveDateTime) -> impl Strategy<Value = (Goal, Option<Goal>)> {
+    prop_oneof![3 => arb_filter(), 1 => arb_midnight_filter()]
+        .prop_flat_map(move |filter| {
+            (
+                Just(filter.clone()),
+                arb_budget_config(filter),
+                arb_not_on(cal_start),
+                prop::option::of(1..=4usize),
+                prop::collection::vec(arb_window(), 0..=1),
+                arb_tags(),
+            )
+        })
+        .prop_map(
+            |(filter, budget_config, not_on, child_duration, preferred_windows, tags)| {
+                let budget = Goal {
+                    id: String::new(),
+                    start: NaiveDateTime::default(),
+                    deadline: None,
+                    deadline_mode: DeadlineMode::Hard,
+                    budget_config: Some(budget_config),
+                    filters: Some(filter),
+                    min_duration: None,
+                    title: String::new(),
+                    children: None,
+                    not_on,
+                    preferred_windows,
+                    tags,
+                    distribution: None,
+                    max_per_day: None,
+                    placement: Placement::Asap,
+                    day_constraints: vec![],
+                };
+                let child = child_duration.map(|duration| Goal {
+                    id: String::new(),
+                    start: NaiveDateTime::default(),
+                    deadline: None,
+                    deadline_mode: DeadlineMode::Hard,
+                    budget_config: None,
+                    filters: None,
+                    min_duration: Some(duration),
+                    title: String::new(),
+                    children: None,
+                    not_on: None,
+                    preferred_windows: vec![],
+                    tags: vec![],
+                    distribution: None,
+                    max_per_day: None,
+                    placement: Placement::Asap,
+                    day_constraints: vec![],
+                });
+                (budget, child)
+            },
+        )
+}
+
+fn arb_input() -> impl Strategy<Value = TestInput> {
+    arb_calendar_start().prop_flat_map(|cal_start| {
+        (
+            prop::collection::vec(arb_simple_goal(cal_start), 0..5),
+            prop::collection::vec(arb_budget_goal(cal_start), 0..3),
+            prop::collection::vec(prop::option::of(1..=6usize), 8),
+            prop::collection::vec(prop::option::of(1..=4i64), 8),
+            prop::option::of(0..48 * 60i64),
+            any::<bool>(),
+            any::<bool>(),
+            any::<bool>(),
+            prop::collection::vec(arb_tag_cap(), 0..=2),
+            prop::option::of(4..=16usize),
+            prop::collection::vec(arb_reserved_free_time(), 0..=1),
+            prop::option::of(prop::sample::select(vec![
+                WeekStart::Monday,
+                WeekStart::Sunday,
+                WeekStart::Iso,
+            ])),
+        )
+            .prop_map(
+                move |(
+                    simple_goals,
+                    budget_goals,
+                    spent,
+                    missed,
+                    now,
+                    free_time_segments,
+                    share_child,
+                    nest_budget,
+                    tag_caps,
+                    max_hours_per_day,
+                    reserved_free_time,
+                    week_start,
+                )| {
+                    let mut goals = vec![];
+                    for (index, mut goal) in simple_goals.into_iter().enumerate() {
+                        goal.id = format!("simple-{}", index);
+                        goal.title = goal.id.clone();
+                        goals.push(goal);
+                    }
+                    for (index, (mut budget, child)) in budget_goals.into_iter().enumerate() {
+                        budget.id = format!("budget-{}", index);
+                        budget.title = budget.id.clone();
+                        if let Some(mut child) = child {
+                            child.id = format!("budget-{}-child", index);
+                            child.title = child.id.clone();
+                            budget.children = Some(vec![child.id.clone()]);
+                            goals.push(child);
+                        }
+                        goals.push(budget);
+                    }
+                    // a budget can be nested inside another one
+                    if nest_budget && goals.iter().any(|goal| goal.id == "budget-1") {
+                        if let Some(budget) = goals.iter_mut().find(|goal| goal.id == "budget-0") {
+                            budget
+                                .children
+                                .get_or_insert_with(Vec::new)
+                                .push("budget-1".to_string());
+                        }
+                    }
+                    // a goal can be in more than one budget
+                    if share_child && goals.iter().any(|goal| goal.id == "budget-0-child") {
+                        if let Some(budget) = goals.iter_mut().find(|goal| goal.id == "budget-1") {
+                            budget
+                                .children
+                                .get_or_insert_with(Vec::new)
+                                .push("budget-0-child".to_string());
+                        }
+                    }
+                    // some goals already got hours before the calendar
+                    let hours_spent = goals
+                        .iter()
+                        .zip(spent)
+                        .filter_map(|(goal, hours)| {
+                            hours.map(|hours| HoursSpent {
+                                goalid: goal.id.clone(),
+                                hours,
+                            })
+                        })
+                        .collect();
+                    // and some goals missed a task the day before the calendar
+                    let missed_tasks = goals
+                        .iter()
+                        .zip(missed)
+                        .filter_map(|(goal, hours)| {
+                            hours.map(|hours| MissedTask {
+                                goalid: goal.id.clone(),
+                                start: cal_start - Duration::hours(12),
+                                deadline: cal_start - Duration::hours(12 - hours),
+                            })
+                        })
+                        .collect();
+                    TestInput {
+                        start_date: cal_start,
+                        end_date: cal_start + Duration::days(CALENDAR_DAYS),
+                        goals,
+                        hours_spent,
+                        missed_tasks,
+                        now: now.map(|minutes| cal_start + Duration::minutes(minutes)),
+                        free_time_segments,
+                        tag_caps,
+                        max_hours_per_day,
+                        reserved_free_time,
+                        week_start,
+                    }
+                },
+            )
+    })
+}
+
+fn occupied_tasks(output: &FinalTasks) -> Vec<&Task> {
+    output
+        .scheduled
+        .iter()
+        .flat_map(|day| day.tasks.iter())
+        .filter(|task| !["free", "blocked", "past"].contains(&task.goalid.as_str()))
+        .collect()
+}
+
+fn hours_of(task: &Task) -> impl Iterator<Item = NaiveDateTime> + '_ {
+    (0..task.duration as i64).map(move |offset| task.start + Duration::hours(offset))
+}
+
+fn is_in_filter(filter: &Filter, hour: NaiveDateTime) -> bool {
+    if !filter.on_days.contains(&hour.weekday()) {
+        return false;
+    }
+    windows_on(filter, hour.weekday())
+        .into_iter()
+        .any(|window| is_in_window(window, hour.hour() as usize))
+}
+
+fn is_descendant_of(goal_id: &str, ancestor: &Goal, goals: &BTreeMap<String, Goal>) -> bool {
+    ancestor.children.iter().flatten().any(|child| {
+        child == goal_id
+            || goals
+                .get(child)
+                .is_some_and(|child| is_descendant_of(goal_id, child, goals))
+    })
+}
+
+/// The budget goals a goal counts toward, including the ones its budgets are nested in
+fn budgets_of<'a>(goal_id: &str, goals: &'a BTreeMap<String, Goal>) -> Vec<&'a Goal> {
+    goals
+        .values()
+        .filter(|goal| {
+            goal.budget_config.is_some()
+                && (goal.id == goal_id || is_descendant_of(goal_id, goal, goals))
+        })
+        .collect()
+}
+
+fn is_in_budget(goal_id: &str, budget: &Goal, goals: &BTreeMap<String, Goal>) -> bool {
+    budgets_of(goal_id, goals)
+        .iter()
+        .any(|goal| goal.id == budget.id)
+}
+
+fn check_no_overlaps(output: &FinalTasks) -> Result<(), TestCaseError> {
+    let mut tasks = occupied_tasks(output);
+    tasks.sort_by_key(|task| task.start);
+    for pair in tasks.windows(2) {
+        prop_assert!(
+            pair[0].deadline <= pair[1].start,
+            "Task {:?} overlaps with {:?}",
+            pair[0],
+            pair[1]
+        );
+    }
+    Ok(())
+}
+
+fn check_goal_constraints(
+    output: &FinalTasks,
+    goals: &BTreeMap<String, Goal>,
+) -> Result<(), TestCaseError> {
+    for task in occupied_tasks(output) {
+        let goal = &goals[&task.goalid];
+        // goals in budgets have to stay inside the filters of all of them
+        let budgets = budgets_of(&goal.id, goals);
+        let filters: Vec<&Filter> = if budgets.is_empty() {
+            goal.filters.iter().collect()
+        } else {
+            budgets
+                .iter()
+                .filter_map(|budget| budget.filters.as_ref())
+                .collect()
+        };
+        for hour in hours_of(task) {
+            for filter in &filters {
+                prop_assert!(
+                    is_in_filter(filter, hour),
+                    "Task {:?} outside of filter {:?}",
+                    task,
+                    filter
+                );
+            }
+            if let Some(not_on) = &goal.not_on {
+                prop_assert!(
+                    not_on
+                        .iter()
+                        .all(|slot| hour < slot.start || hour >= slot.end),
+                    "Task {:?} scheduled during not_on {:?}",
+                    task,
+                    not_on
+                );
+            }
+            prop_assert!(
+                hour >= goal.start,
+                "Task {:?} starts before goal start {:?}",
+                task,
+                goal.start
+            );
+            if let Some(deadline) = goal.deadline {
+                prop_assert!(
+                    hour < deadline || goal.deadline_mode == DeadlineMode::Soft,
+                    "Task {:?} ends after goal deadline {:?}",
+                    task,
+                    deadline
+                );
+            }
+        }
+        if let Some(deadline) = goal.deadline {
+            let task_end = task.start + Duration::hours(task.duration as i64);
+            let hours_late = (task_end - deadline).num_hours();
+            prop_assert_eq!(
+                task.hours_late,
+                (hours_late > 0).then_some(hours_late as usize),
+                "Task {:?} reports its lateness wrong",
+                task
+            );
+        }
+    }
+    Ok(())
+}
+
+fn check_budget_maxima(
+    input: &TestInput,
+    output: &FinalTasks,
+    goals: &BTreeMap<String, Goal>,
+) -> Result<(), TestCaseError> {
+    for budget in goals.values().filter(|goal| goal.budget_config.is_some()) {
+        let config = budget.budget_config.as_ref().unwrap();
+        let filter = budget.filters.as_ref().unwrap();
+        let mut hours_per_day: BTreeMap<NaiveDate, usize> = BTreeMap::new();
+        let mut hours_per_week: BTreeMap<i64, usize> = BTreeMap::new();
+        for task in occupied_tasks(output) {
+            if !is_in_budget(&task.goalid, budget, goals) {
+                continue;
+            }
+            for hour in hours_of(task) {
+                let index = (hour - input.start_date).num_hours();
+                let mut day = hour.date();
+                if hour < budget_day_start(filter, day) {
+                    day = day.pred_opt().unwrap();
+                }
+                *hours_per_day.entry(day).or_default() += 1;
+                *hours_per_week
+                    .entry((index + input.hours_into_first_week()).div_euclid(24 * 7))
+                    .or_default() += 1;
+            }
+        }
+        for (day, hours) in hours_per_day {
+            let weekday = day.weekday();
+            prop_assert!(
+                hours <= config.max_on(weekday),
+                "Budget {} has {} hours on day {}, max is {}",
+                budget.id,
+                hours,
+                day,
+                config.max_on(weekday)
+            );
+        }
+        // hours spent before the calendar count toward the first week, up to its max
+        let spent: usize = input
+            .hours_spent
+            .iter()
+            .filter(|spent| is_in_budget(&spent.goalid, budget, goals))
+            .map(|spent| spent.hours)
+            .sum();
+        *hours_per_week.entry(0).or_default() += spent.min(config.max_per_week);
+        for (week, hours) in hours_per_week {
+            prop_assert!(
+                hours <= config.max_per_week,
+                "Budget {} has {} hours in week {}, max is {}",
+                budget.id,
+                hours,
+                week,
+                config.max_per_week
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Goals without a budget stay under their own max per day
+fn check_goal_max_per_day(
+    input: &TestInput,
+    output: &FinalTasks,
+    goals: &BTreeMap<String, Goal>,
+) -> Result<(), TestCaseError> {
+    for goal in goals.values() {
+        let Some(max_per_day) = goal.max_per_day else {
+            continue;
+        };
+        let mut hours_per_day: BTreeMap<i64, usize> = BTreeMap::new();
+        for task in occupied_tasks(output) {
+            if task.goalid != goal.id {
+                continue;
+            }
+            for hour in hours_of(task) {
+                let index = (hour - input.start_date).num_hours();
+                *hours_per_day.entry(index.div_euclid(24)).or_default() += 1;
+            }
+        }
+        for (day, hours) in hours_per_day {
+            prop_assert!(
+                hours <= max_per_day,
+                "Goal {} has {} hours on day {}, max is {}",
+                &goal.id,
+                hours,
+                day,
+                max_per_day
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The goals with a capped tag, and the goals below them, together stay under its caps
+fn check_tag_caps(
+    input: &TestInput,
+    output: &FinalTasks,
+    goals: &BTreeMap<String, Goal>,
+) -> Result<(), TestCaseError> {
+    for tag_cap in &input.tag_caps {
+        let is_tagged = |goal_id: &str| {
+            goals.values().any(|goal| {
+                goal.tags.contains(&tag_cap.tag)
+                    && (goal.id == goal_id || is_descendant_of(goal_id, goal, goals))
+            })
+        };
+        let mut hours_per_day: BTreeMap<i64, usize> = BTreeMap::new();
+        let mut hours_per_week: BTreeMap<i64, usize> = BTreeMap::new();
+        for task in occupied_tasks(output) {
+            if !is_tagged(&task.goalid) {
+                continue;
+            }
+            for hour in hours_of(task) {
+                let index = (hour - input.start_date).num_hours();
+                *hours_per_day.entry(index.div_euclid(24)).or_default() += 1;
+                *hours_per_week
+                    .entry((index + input.hours_into_first_week()).div_euclid(24 * 7))
+                    .or_default() += 1;
+            }
+        }
+        if let Some(max_per_day) = tag_cap.max_per_day {
+            for (day, hours) in hours_per_day {
+                prop_assert!(
+                    hours <= max_per_day,
+                    "Tag {} has {} hours on day {}, max is {}",
+                    &tag_cap.tag,
+                    hours,
+                    day,
+                    max_per_day
+                );
+            }
+        }
+        if let Some(max_per_week) = tag_cap.max_per_week {
+            for (week, hours) in hours_per_week {
+                prop_assert!(
+                    hours <= max_per_week,
+                    "Tag {} has {} hours in week {}, max is {}",
+                    &tag_cap.tag,
+                    hours,
+                    week,
+                    max_per_week
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// All goals together stay under the max hours per day, and leave the reserved free time free
+fn check_workload_limits(input: &TestInput, output: &FinalTasks) -> Result<(), TestCaseError> {
+    let hours: Vec<NaiveDateTime> = occupied_tasks(output)
+        .into_iter()
+        .flat_map(hours_of)
+        .collect();
+    if let Some(max_hours_per_day) = input.max_hours_per_day {
+        let mut hours_per_day: BTreeMap<i64, usize> = BTreeMap::new();
+        for hour in &hours {
+            *hours_per_day
+                .entry((*hour - input.start_date).num_hours().div_euclid(24))
+                .or_default() += 1;
+        }
+        for (day, scheduled) in hours_per_day {
+            prop_assert!(
+                scheduled <= max_hours_per_day,
+                "Day {} has {} hours, max is {}",
+                day,
+                scheduled,
+                max_hours_per_day
+            );
+        }
+    }
+    for reserved in &input.reserved_free_time {
+        let window = (reserved.after_time, reserved.before_time);
+        let window_hours = (0..24)
+            .filter(|hour_of_day| is_in_window(window, *hour_of_day))
+            .count() as i64;
+        for day in -1..CALENDAR_DAYS {
+            let window_start =
+                input.start_date + Duration::hours(day * 24 + reserved.after_time as i64);
+            let window_end = window_start + Duration::hours(window_hours);
+            let scheduled = hours
+                .iter()
+                .filter(|hour| **hour >= window_start && **hour < window_end)
+                .count();
+            prop_assert!(
+                scheduled + reserved.hours.min(window_hours as usize) <= window_hours as usize,
+                "Window from {} has {} hours scheduled, {} of {} should stay free",
+                window_start,
+                scheduled,
+                reserved.hours,
+                window_hours
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Nothing gets placed in an hour that has already started
+fn check_nothing_before_now(input: &TestInput, output: &FinalTasks) -> Result<(), TestCaseError> {
+    let Some(now) = input.now else {
+        return Ok(());
+    };
+    for task in occupied_tasks(output) {
+        prop_assert!(
+            task.start + Duration::hours(1) > now,
+            "Task {:?} starts before now {:?}",
+            task,
+            now
+        );
+    }
+    Ok(())
+}
+
+/// The budget report should count exactly the hours of the budget's tasks in each window
+fn check_budget_progress(
+    output: &FinalTasks,
+    goals: &BTreeMap<String, Goal>,
+) -> Result<(), TestCaseError> {
+    for progress in &output.budgets {
+        for window in &progress.windows {
+            let is_cut_off =
+                window.period_end_date_time - window.period_start_date_time < Duration::days(7);
+            prop_assert_eq!(
+                window.partial,
+                window.window_type == TimeBudgetType::Week && is_cut_off,
+                "Budget {} reports {:?} as partial or not",
+                &progress.id,
+                window
+            );
+            let scheduled = occupied_tasks(output)
+                .into_iter()
+                .filter(|task| is_in_budget(&task.goalid, &goals[&progress.id], goals))
+                .flat_map(hours_of)
+                .filter(|hour| {
+                    *hour >= window.period_start_date_time && *hour < window.period_end_date_time
+                })
+                .count();
+            prop_assert_eq!(
+                scheduled,
+                window.scheduled,
+                "Budget {} reports {:?} but has {} hours there",
+                &progress.id,
+                window,
+                scheduled
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Budgets with consistent time report the hour of the day their hours started at, others don't
+fn check_anchor_hours(
+    output: &FinalTasks,
+    goals: &BTreeMap<String, Goal>,
+) -> Result<(), TestCaseError> {
+    for progress in &output.budgets {
+        let budget_goal = &goals[&progress.id];
+        let consistent_time = budget_goal
+            .budget_config
+            .as_ref()
+            .is_some_and(|config| config.consistent_time);
+        let hours: Vec<NaiveDateTime> = occupied_tasks(output)
+            .into_iter()
+            .filter(|task| is_in_budget(&task.goalid, budget_goal, goals))
+            .flat_map(hours_of)
+            .collect();
+        match progress.anchor_hour {
+            None => prop_assert!(
+                !consistent_time || hours.is_empty(),
+                "Budget {} has consistent time and hours, but no anchor hour",
+                &progress.id
+            ),
+            Some(anchor_hour) => prop_assert!(
+                consistent_time && hours.iter().any(|hour| hour.hour() as usize == anchor_hour),
+                "Budget {} reports anchor hour {} without hours at it",
+                &progress.id,
+                anchor_hour
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Tasks of goals with preferred windows should report if all their hours are inside them
+fn check_preference_report(
+    output: &FinalTasks,
+    goals: &BTreeMap<String, Goal>,
+) -> Result<(), TestCaseError> {
+    for task in occupied_tasks(output) {
+        let goal = &goals[&task.goalid];
+        let expected = (!goal.preferred_windows.is_empty()).then(|| {
+            hours_of(task).all(|hour| {
+                goal.preferred_windows
+                    .iter()
+                    .any(|window| window.contains_hour(hour.hour() as usize))
+            })
+        });
+        prop_assert_eq!(
+            task.in_preferred_window,
+            expected,
+            "Task {:?} reports its preference wrong",
+            task
+        );
+    }
+    Ok(())
+}
+
+/// Goals in more than one budget get reported if no hour is inside the filters of all of them
+fn check_budget_conflicts(
+    output: &FinalTasks,
+    goals: &BTreeMap<String, Goal>,
+) -> Result<(), TestCaseError> {
+    for goal in goals.values() {
+        let budgets = budgets_of(&goal.id, goals);
+        let has_hours_in_common = ALL_DAYS.iter().any(|weekday| {
+            (0..24).any(|hour_of_day| {
+                budgets.iter().all(|budget| {
+                    let filter = budget.filters.as_ref().unwrap();
+                    filter.on_days.contains(weekday)
+                        && windows_on(filter, *weekday)
+                            .into_iter()
+                            .any(|window| is_in_window(window, hour_of_day))
+                })
+            })
+        });
+        let is_reported = output
+            .budget_conflicts
+            .iter()
+            .any(|conflict| conflict.goalid == goal.id);
+        prop_assert_eq!(
+            is_reported,
+            budgets.len() > 1 && !has_hours_in_common,
+            "Goal {} in budgets {:?} has its conflict reported wrong",
+            &goal.id,
+            budgets.iter().map(|budget| &budget.id).collect::<Vec<_>>()
+        );
+    }
+    Ok(())
+}
+
+fn check_hours_accounted_for(
+    input: &TestInput,
+    output: &FinalTasks,
+    goals: &BTreeMap<String, Goal>,
+) -> Result<(), TestCaseError> {
+    for goal in goals.values() {
+        // goals without deadline may be placed best effort over the calendar edge,
+        // and budget goals request hours per day/week instead of a single duration
+        if goal.deadline.is_none() || !budgets_of(&goal.id, goals).is_empty() {
+            continue;
+        }
+        // hours of goals with a deadline after the calendar are left for a later schedule
+        if goal.deadline.unwrap() > input.end_date {
+            continue;
+        }
+        let spent: usize = input
+            .hours_spent
+            .iter()
+            .filter(|spent| spent.goalid == goal.id)
+            .map(|spent| spent.hours)
+            .sum();
+        let missed: usize = input
+            .missed_tasks
+            .iter()
+            .filter(|missed| missed.goalid == goal.id)
+            .map(|missed| (missed.deadline - missed.start).num_hours() as usize)
+            .sum();
+        let requested = goal.min_duration.unwrap().saturating_sub(spent) + missed;
+        let scheduled: usize = occupied_tasks(output)
+            .iter()
+            .filter(|task| task.goalid == goal.id)
+            .map(|task| task.duration)
+            .sum();
+        let missing: usize = output
+            .impossible
+            .iter()
+            .filter(|impossible| impossible.id == goal.id)
+            .map(|impossible| impossible.hours_missing)
+            .sum();
+        prop_assert_eq!(
+            scheduled + missing,
+            requested,
+            "Scheduled {} + missing {} hours for goal {}",
+            scheduled,
+            missing,
+            goal.id
+        );
+    }
+    Ok(())
+}
+
+/// The verifier should only find budget minimums that couldn't be met
+fn check_verifier_agrees(input: &TestInput, output: &FinalTasks) -> Result<(), TestCaseError> {
+    let violations: Vec<Violation> = verify(&input.to_input(), output)
+        .into_iter()
+        .filter(|violation| !matches!(violation, Violation::BudgetMinNotMet { .. }))
+        .collect();
+    prop_assert!(violations.is_empty(), "Verifier found {:?}", violations);
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn schedule_respects_invariants(input in arb_input()) {
+        let output = run_scheduler_with_input(&input.to_input());
+        let goals: BTreeMap<String, Goal> = input
+            .goals
+            .iter()
+            .map(|goal| (goal.id.clone(), goal.clone()))
+            .collect();
+
+        check_no_overlaps(&output)?;
+        check_goal_constraints(&output, &goals)?;
+        check_nothing_before_now(&input, &output)?;
+        check_budget_maxima(&input, &output, &goals)?;
+        check_goal_max_per_day(&input, &output, &goals)?;
+        check_tag_caps(&input, &output, &goals)?;
+        check_workload_limits(&input, &output)?;
+        check_budget_progress(&output, &goals)?;
+        check_anchor_hours(&output, &goals)?;
+        check_preference_report(&output, &goals)?;
+        check_budget_conflicts(&output, &goals)?;
+        check_hours_accounted_for(&input, &output, &goals)?;
+        check_verifier_agrees(&input, &output)?;
+    }
+}
@@ -0,0 +1,275 @@
+extern crate scheduler;
+
+use std::fs;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+use scheduler::models::goal::DayRelation;
+use scheduler::models::task::{FinalTasks, Task};
+use scheduler::services::verifier::{verify, Violation};
+use scheduler::technical::input_output;
+use scheduler::technical::input_output::Input;
+
+fn midnight(year: i32, month: u32, day: u32) -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(year, month, day)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+}
+
+fn schedule(directory: &str) -> (Input, FinalTasks) {
+    let input =
+        input_output::get_input_from_json(format!("./tests/jsons/stable/{}/input.json", directory))
+            .unwrap();
+    let output = scheduler::run_scheduler_with_input(&input);
+    (input, output)
+}
+
+/// Fixtures whose schedule is expected to break a rule - each one has its own test below
+const EXPECTED_TO_HAVE_VIOLATIONS: [&str; 1] = ["workload-limits"];
+
+/// Schedules a fixture, changes the task with the title on a day of the output, and verifies the changed output
+fn verify_changed_task(
+    directory: &str,
+    day: usize,
+    title: &str,
+    change: impl FnOnce(&mut Task),
+) -> Vec<Violation> {
+    let (input, mut output) = schedule(directory);
+    let task = output.scheduled[day]
+        .tasks
+        .iter_mut()
+        .find(|task| task.title == title)
+        .unwrap();
+    change(task);
+    verify(&input, &output)
+}
+
+fn lengthen(task: &mut Task, hours: usize) {
+    task.duration += hours;
+    task.deadline += Duration::hours(hours as i64);
+}
+
+fn shift(task: &mut Task, by: Duration) {
+    task.start += by;
+    task.deadline += by;
+}
+
+#[test]
+fn scheduled_output_has_no_violations() {
+    let mut directories: Vec<String> = fs::read_dir("./tests/jsons/stable")
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|directory| !EXPECTED_TO_HAVE_VIOLATIONS.contains(&directory.as_str()))
+        .collect();
+    directories.sort();
+    for directory in &directories {
+        let (input, output) = schedule(directory);
+        assert_eq!(verify(&input, &output), vec![], "{}", directory);
+    }
+}
+
+/// The max hours per day leave no room for the min per day of work on the first days.
+/// The schedule reports those hours as impossible, limited by the max hours per day.
+#[test]
+fn workload_limits_leave_budget_min_unmet() {
+    let (input, output) = schedule("workload-limits");
+
+    assert_eq!(
+        verify(&input, &output),
+        vec![
+            Violation::BudgetMinNotMet {
+                goalid: "1".to_string(),
+                period_start_date_time: midnight(2022, 1, 3),
+                period_end_date_time: midnight(2022, 1, 4),
+                scheduled: 3,
+                min: 5,
+            },
+            Violation::BudgetMinNotMet {
+                goalid: "1".to_string(),
+                period_start_date_time: midnight(2022, 1, 4),
+                period_end_date_time: midnight(2022, 1, 5),
+                scheduled: 3,
+                min: 5,
+            },
+        ]
+    );
+}
+
+#[test]
+fn task_moved_onto_other_task_is_overlap() {
+    let violations = verify_changed_task("basic-1", 0, "shopping", |task| {
+        shift(task, Duration::hours(-1))
+    });
+
+    assert_eq!(
+        violations,
+        vec![Violation::Overlap {
+            taskid: 1,
+            other_taskid: 2,
+        }]
+    );
+}
+
+#[test]
+fn task_moved_into_not_on_is_reported() {
+    let violations = verify_changed_task("not-on", 0, "shopping", |task| {
+        shift(task, Duration::hours(-1))
+    });
+
+    assert_eq!(
+        violations,
+        vec![Violation::InNotOn {
+            taskid: 1,
+            goalid: "1".to_string(),
+            hour: midnight(2022, 1, 1) + Duration::hours(10),
+        }]
+    );
+}
+
+#[test]
+fn budget_max_per_day_exceeded_is_reported() {
+    let violations = verify_changed_task("sleep-1", 1, "sleep", |task| lengthen(task, 3));
+
+    assert!(violations.contains(&Violation::BudgetMaxExceeded {
+        goalid: "1".to_string(),
+        period_start_date_time: midnight(2022, 1, 1) + Duration::hours(22),
+        period_end_date_time: midnight(2022, 1, 2) + Duration::hours(22),
+        scheduled: 11,
+        max: 8,
+    }));
+}
+
+#[test]
+fn removed_completed_task_is_reported() {
+    let (input, mut output) = schedule("task-done-today");
+    let completed = &input.tasks_completed_today[0];
+    output.scheduled[0].tasks.retain(|task| {
+        !(task.goalid == completed.goalid
+            && task.start < completed.deadline
+            && task.deadline > completed.start)
+    });
+
+    let violations = verify(&input, &output);
+
+    assert!(violations.contains(&Violation::CompletedTaskNotPreserved {
+        goalid: completed.goalid.clone(),
+        start: completed.start,
+        deadline: completed.deadline,
+    }));
+}
+
+#[test]
+fn task_moved_before_now_is_reported() {
+    let violations =
+        verify_changed_task("now", 0, "email", |task| shift(task, Duration::hours(-4)));
+
+    assert_eq!(
+        violations,
+        vec![Violation::BeforeNow {
+            taskid: 3,
+            goalid: "1".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn task_flagged_completed_today_before_now_is_reported() {
+    let violations = verify_changed_task("now", 0, "email", |task| {
+        shift(task, Duration::hours(-4));
+        task.completed_today = true;
+    });
+
+    assert_eq!(
+        violations,
+        vec![Violation::BeforeNow {
+            taskid: 3,
+            goalid: "1".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn tag_cap_exceeded_is_reported() {
+    let violations = verify_changed_task("tag-caps", 0, "read book", |task| {
+        task.goalid = "1".to_string();
+        task.title = "series".to_string();
+    });
+
+    assert_eq!(
+        violations,
+        vec![
+            Violation::TagCapExceeded {
+                tag: "screen".to_string(),
+                period_start_date_time: midnight(2022, 1, 3),
+                period_end_date_time: midnight(2022, 1, 4),
+                scheduled: 4,
+                max: 2,
+            },
+            Violation::TagCapExceeded {
+                tag: "screen".to_string(),
+                period_start_date_time: midnight(2022, 1, 3),
+                period_end_date_time: midnight(2022, 1, 10),
+                scheduled: 16,
+                max: 14,
+            },
+        ]
+    );
+}
+
+#[test]
+fn max_hours_per_day_exceeded_is_reported() {
+    let violations = verify_changed_task("workload-limits", 0, "work", |task| lengthen(task, 2));
+
+    assert!(violations.contains(&Violation::MaxHoursPerDayExceeded {
+        period_start_date_time: midnight(2022, 1, 3),
+        period_end_date_time: midnight(2022, 1, 4),
+        scheduled: 9,
+        max: 7,
+    }));
+}
+
+#[test]
+fn budget_max_per_week_is_checked_from_week_start() {
+    let violations = verify_changed_task("week-start", 9, "run", |task| lengthen(task, 1));
+
+    let monday = midnight(2022, 1, 10);
+    assert!(violations.contains(&Violation::BudgetMaxExceeded {
+        goalid: "1".to_string(),
+        period_start_date_time: monday,
+        period_end_date_time: monday + Duration::days(7),
+        scheduled: 7,
+        max: 6,
+    }));
+}
+
+#[test]
+fn goal_max_per_day_exceeded_is_reported() {
+    let violations =
+        verify_changed_task("distribution", 0, "clean garage", |task| lengthen(task, 1));
+
+    assert_eq!(
+        violations,
+        vec![Violation::GoalMaxPerDayExceeded {
+            goalid: "2".to_string(),
+            period_start_date_time: midnight(2022, 1, 3),
+            period_end_date_time: midnight(2022, 1, 4),
+            scheduled: 4,
+            max: 3,
+        }]
+    );
+}
+
+#[test]
+fn not_same_day_constraint_broken_is_reported() {
+    let violations = verify_changed_task("day-constraints", 0, "run", |task| {
+        shift(task, Duration::days(1))
+    });
+
+    assert!(violations.contains(&Violation::DayConstraintBroken {
+        goalid: "4".to_string(),
+        other_goalid: "3".to_string(),
+        relation: DayRelation::NotSameDay,
+        day: NaiveDate::from_ymd_opt(2022, 1, 4).unwrap(),
+    }));
+}